@@ -0,0 +1,51 @@
+use std::marker::PhantomData;
+use ecs;
+use rtree::{SpatialIndex, Touch};
+use Step;
+
+/// marks an entity as taking part in collision detection
+#[derive(Clone, Default)]
+pub struct Collider;
+
+impl ecs::Component for Collider {
+    type Storage = ecs::NullStorage<Collider>;
+}
+
+/// every pair of colliders whose locations overlapped this step,
+/// each pair is only listed once
+#[derive(Clone, Debug, Default)]
+pub struct Contacts(pub Vec<(ecs::Entity, ecs::Entity)>);
+
+/// Finds overlapping colliders using the spatial index resource `S`,
+/// must run after the spatial index has been updated
+pub struct System<S>(PhantomData<S>);
+
+impl<S> System<S> {
+    pub fn new() -> System<S> {
+        System(PhantomData)
+    }
+}
+
+impl<S> ecs::System<Step> for System<S>
+    where S: SpatialIndex<ecs::Entity> + Send + Sync + 'static
+{
+    fn run(&mut self, arg: ecs::RunArg, step: Step) {
+        let (collider, tree, mut contacts) = arg.fetch(|w| {
+            (w.read::<Collider>(),
+             w.read_resource::<S>(),
+             w.write_resource::<Contacts>())
+        });
+
+        if !step.is_game() {
+            return
+        }
+
+        contacts.0.clear();
+
+        for ((_, &a), (_, &b)) in tree.intersecting_pairs(Touch::Overlaps) {
+            if collider.get(a).is_some() && collider.get(b).is_some() {
+                contacts.0.push((a, b));
+            }
+        }
+    }
+}
@@ -10,6 +10,10 @@ extern crate specs as ecs;
 
 #[cfg(test)]
 extern crate quickcheck;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+#[cfg(feature = "mmap")]
+extern crate memmap;
 
 mod renderer;
 mod camera;
@@ -17,17 +21,24 @@ mod input;
 mod transform;
 mod movement;
 mod rtree;
+mod spatial;
+mod contact;
 
 use glutin::Event;
 use glutin::VirtualKeyCode as Key;
 use cgmath::Vector3;
 use collision::{Plane, Intersect};
 use rtree::{RTree, Rectangle, Point};
+use movement::Q8p24;
 use ecs::Join;
 pub use transform::MovingTo;
 
 const SCALE: f32 = 0.1;
 
+/// the spatial index the systems share, any `SpatialIndex` will do
+/// though the F3 dump only knows how to describe an `RTree`
+type Index = RTree<ecs::Entity>;
+
 #[derive(Copy, Clone, Debug)]
 pub enum Step {
     Game(u64),
@@ -85,6 +96,7 @@ fn main() {
     world.register::<BulletMarker>();
     world.register::<movement::Movement>();
     world.register::<Decay>();
+    world.register::<contact::Collider>();
 
 
     let builder = glutin::WindowBuilder::new()
@@ -94,7 +106,8 @@ fn main() {
 
     let eid = world.create_now()
                    .with(PreviewMarker)
-                   .with(movement::Movement::new(0., 0.))
+                   .with(contact::Collider)
+                   .with(movement::Movement::new(0., 0.).with_friction(0.5))
                    .with(transform::Location(
                         rtree::Rectangle{
                             min: Point{x: -1, y: -1},
@@ -109,16 +122,20 @@ fn main() {
     let (mut renderer, window) = renderer::Renderer::new(builder);
     world.add_resource(input::Events::new(&window));
     world.add_resource(camera::Camera::new());
-    world.add_resource(RTree::<ecs::Entity>::new());
+    world.add_resource(Index::new());
+    world.add_resource(contact::Contacts::default());
+    world.add_resource(spatial::Deleted::default());
     world.add_resource(Player(eid));
 
     let mut sim = ecs::Planner::<Step>::new(world, 4);
     sim.add_system(InputHandler, "Input Handler", 16);
     sim.add_system(ShootShit, "Create box", 15);
     sim.add_system(movement::System, "Movement", 14);
-    sim.add_system(CameraSystem, "Camera System", 13);
-    sim.add_system(DecaySystem, "Decay System", 12);
-    sim.add_system(transform::LocationToTransform, "Location Sync", 11);
+    sim.add_system(spatial::System::<Index>::new(), "Spatial Index", 13);
+    sim.add_system(contact::System::<Index>::new(), "Contacts", 12);
+    sim.add_system(CameraSystem, "Camera System", 11);
+    sim.add_system(DecaySystem, "Decay System", 10);
+    sim.add_system(transform::LocationToTransform, "Location Sync", 9);
 
     let start = std::time::SystemTime::now();
     let mut index = 0;
@@ -145,11 +162,15 @@ struct InputHandler;
 
 impl ecs::System<Step> for InputHandler {
     fn run(&mut self, arg: ecs::RunArg, step: Step) {
-        let (mut camera, input, player, mut mov) = arg.fetch(|w| {
+        let (mut camera, input, player, mut mov, tree, deleted, location, collider) = arg.fetch(|w| {
             (w.write_resource::<camera::Camera>(),
              w.read_resource::<input::Events>(),
              w.read_resource::<Player>(),
-             w.write::<movement::Movement>())
+             w.write::<movement::Movement>(),
+             w.read_resource::<Index>(),
+             w.read_resource::<spatial::Deleted>(),
+             w.read::<transform::Location>(),
+             w.read::<contact::Collider>())
         });
 
         if !step.is_game() {
@@ -163,7 +184,7 @@ impl ecs::System<Step> for InputHandler {
         } * SCALE;
 
         for e in &input.events {
-            use glutin::MouseScrollDelta;
+            use glutin::{ElementState, MouseScrollDelta};
             match e {
                 &Event::MouseWheel(MouseScrollDelta::LineDelta(_, x), _) => {
                     camera.position.z -= 2. * x * SCALE;
@@ -171,6 +192,18 @@ impl ecs::System<Step> for InputHandler {
                 &Event::MouseWheel(MouseScrollDelta::PixelDelta(_, x), _) => {
                     camera.position.z -= 2. * x * SCALE / 10.;
                 }
+                // dump the shape of the spatial index
+                &Event::KeyboardInput(ElementState::Pressed, _, Some(Key::F3)) => {
+                    println!("{:?}", tree.stats());
+                    if let Err(err) = tree.validate() {
+                        println!("spatial index is broken: {}", err);
+                    }
+                    if let Some(loc) = location.get(player.0) {
+                        let spatial = spatial::Spatial::new(&*tree, &*deleted);
+                        let near = spatial.query(loc.0.expand_by(64)).with(&collider).count();
+                        println!("{} colliders near the player", near);
+                    }
+                }
                 _ => ()
             }
         }
@@ -190,8 +223,10 @@ impl ecs::System<Step> for InputHandler {
             _ => (0., 0.).into()
         };
 
+        // with half of the speed lost each tick the player settles at
+        // the same speed as the acceleration, but eases in and out
         let movement = mov.get_mut(player.0).unwrap();
-        movement.vector = left_right + up_down;
+        movement.acceleration = left_right + up_down;
     }
 }
 
@@ -231,11 +266,12 @@ struct ShootShit;
 
 impl ecs::System<Step> for ShootShit {
     fn run(&mut self, arg: ecs::RunArg, step: Step) {
-        let (camera, input, player, mut bullet, mut trans, mut mov, mut decay) = arg.fetch(|w| {
+        let (camera, input, player, mut bullet, mut collider, mut trans, mut mov, mut decay) = arg.fetch(|w| {
             (w.read_resource::<camera::Camera>(),
              w.read_resource::<input::Events>(),
              w.read_resource::<Player>(),
              w.write::<BulletMarker>(),
+             w.write::<contact::Collider>(),
              w.write::<transform::Location>(),
              w.write::<movement::Movement>(),
              w.write::<Decay>())
@@ -252,26 +288,30 @@ impl ecs::System<Step> for ShootShit {
             let y = (p.y * 8.).round() as i32;
 
             let pos = *trans.get(player.0).unwrap();
-            let (mx, my) = pos.middle();
+            // twice the middle of the player, so it stays a whole number
+            let mx = pos.0.min.x as i32 + pos.0.max.x as i32;
+            let my = pos.0.min.y as i32 + pos.0.max.y as i32;
 
             if input.is_button_down(glutin::MouseButton::Left) {
                 for x in (x-2)..(x+2) {
                     for y in (y-2)..(y+2) {
-                        let x = x as f32;
-                        let y = y as f32;
-
                         let eid = arg.create();
 
                         trans.insert(eid, transform::Location(pos.0));
                         bullet.insert(eid, BulletMarker);
-
-                        let (dx, dy) = (x - mx, y - my);
-                        let mag = (dx * dx + dy * dy).sqrt();
-
-                        mov.insert(eid, movement::Movement::new(
-                            4. * (x - mx) / mag,
-                            4. * (y - my) / mag
-                        ));
+                        collider.insert(eid, contact::Collider);
+
+                        // the offset is in half sub-cells, 16 to a unit. It is
+                        // halved until it fits in a Q8p24 so the aim never goes
+                        // through a float and every machine agrees
+                        let (mut dx, mut dy) = (2 * x - mx, 2 * y - my);
+                        while std::cmp::max(dx.abs(), dy.abs()) >= 1 << 11 {
+                            dx >>= 1;
+                            dy >>= 1;
+                        }
+                        let dir = movement::Vector::new(Q8p24::from_bits(dx << 20), Q8p24::from_bits(dy << 20));
+                        let v = dir.normalize() * Q8p24::from(4i8);
+                        mov.insert(eid, movement::Movement::new(v.x, v.y));
 
                         decay.insert(eid, Decay(60));
                     }
@@ -294,8 +334,8 @@ struct DecaySystem;
 
 impl ecs::System<Step> for DecaySystem {
     fn run(&mut self, arg: ecs::RunArg, step: Step) {
-        let (eids, mut decay) = arg.fetch(|w| {
-            (w.entities(), w.write::<Decay>())
+        let (eids, mut decay, mut deleted) = arg.fetch(|w| {
+            (w.entities(), w.write::<Decay>(), w.write_resource::<spatial::Deleted>())
         });
 
 
@@ -305,7 +345,7 @@ impl ecs::System<Step> for DecaySystem {
 
         for (eid, d) in (&eids, &mut decay).iter() {
             if d.0 == 0 {
-                arg.delete(eid);
+                deleted.delete(&arg, eid);
             } else {
                 d.0 -= 1;
             }
@@ -0,0 +1,1063 @@
+use std;
+use std::ops::*;
+use std::error::Error;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use std::fmt::{self, Formatter, Debug, Display};
+
+/// an int with 8 bits and 24 fixed point
+pub type Q8p24 = Fixed<i32, F24>;
+
+/// an int with 16 bits and 16 fixed point
+pub type Q16p16 = Fixed<i32, F16>;
+
+/// an int with 32 bits and 32 fixed point
+pub type Q32p32 = Fixed<i64, F32>;
+
+/// the integer a `Fixed` is stored in, all of the maths is done in an
+/// `i128` so there is room for the full result of a multiply
+pub trait Int: Copy + Ord + Hash + Debug {
+    fn bits() -> u32;
+    fn min_value() -> Self;
+    fn max_value() -> Self;
+    fn to_i128(self) -> i128;
+
+    /// keep the low bits of `x`, like an `as` cast
+    fn wrap(x: i128) -> Self;
+}
+
+macro_rules! int {
+    ($t:ident, $bits:expr) => {
+        impl Int for $t {
+            fn bits() -> u32 {
+                $bits
+            }
+
+            fn min_value() -> $t {
+                std::$t::MIN
+            }
+
+            fn max_value() -> $t {
+                std::$t::MAX
+            }
+
+            fn to_i128(self) -> i128 {
+                self as i128
+            }
+
+            fn wrap(x: i128) -> $t {
+                x as $t
+            }
+        }
+    }
+}
+
+int!(i32, 32);
+int!(i64, 64);
+
+/// the number of fractional bits in a `Fixed`
+pub trait Frac: Copy + Ord + Hash + Debug {
+    fn bits() -> u32;
+}
+
+macro_rules! frac {
+    ($t:ident, $bits:expr) => {
+        #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+        pub struct $t;
+
+        impl Frac for $t {
+            fn bits() -> u32 {
+                $bits
+            }
+        }
+    }
+}
+
+frac!(F16, 16);
+frac!(F24, 24);
+frac!(F32, 32);
+
+/// pi, pi/2 and 2pi with 60 fractional bits, rounded to fit a `Fixed`
+const PI_60: i128 = 3622009729038561421;
+const FRAC_PI_2_60: i128 = 1811004864519280711;
+const TAU_60: i128 = 7244019458077122842;
+
+/// the trig tables are stored with this many fractional bits
+const TABLE_BITS: u32 = 24;
+
+/// `sin` of each 1/1024th of a turn over the first quarter turn, with
+/// 24 fractional bits
+const SIN_TABLE: [i64; 257] = [
+    0, 102943, 205882, 308814, 411733, 514638, 617523, 720384,
+    823219, 926023, 1028791, 1131521, 1234209, 1336849, 1439440, 1541976,
+    1644455, 1746871, 1849222, 1951503, 2053710, 2155841, 2257890, 2359854,
+    2461729, 2563511, 2665197, 2766783, 2868265, 2969638, 3070900, 3172046,
+    3273072, 3373976, 3474752, 3575398, 3675909, 3776281, 3876512, 3976596,
+    4076531, 4176312, 4275936, 4375399, 4474698, 4573827, 4672785, 4771567,
+    4870169, 4968587, 5066819, 5164860, 5262706, 5360355, 5457801, 5555042,
+    5652074, 5748893, 5845495, 5941878, 6038037, 6133968, 6229669, 6325135,
+    6420363, 6515349, 6610090, 6704582, 6798821, 6892805, 6986529, 7079990,
+    7173184, 7266109, 7358759, 7451133, 7543226, 7635036, 7726557, 7817788,
+    7908725, 7999364, 8089701, 8179734, 8269459, 8358873, 8447972, 8536753,
+    8625213, 8713348, 8801154, 8888630, 8975771, 9062573, 9149035, 9235152,
+    9320922, 9406340, 9491405, 9576112, 9660458, 9744441, 9828057, 9911303,
+    9994176, 10076672, 10158790, 10240524, 10321873, 10402834, 10483403, 10563577,
+    10643353, 10722729, 10801701, 10880266, 10958422, 11036165, 11113493, 11190402,
+    11266890, 11342953, 11418590, 11493797, 11568571, 11642909, 11716809, 11790268,
+    11863283, 11935852, 12007971, 12079638, 12150850, 12221604, 12291899, 12361731,
+    12431097, 12499995, 12568423, 12636378, 12703856, 12770857, 12837376, 12903413,
+    12968963, 13034026, 13098597, 13162675, 13226258, 13289343, 13351928, 13414009,
+    13475586, 13536656, 13597215, 13657263, 13716797, 13775814, 13834313, 13892291,
+    13949745, 14006675, 14063077, 14118950, 14174291, 14229098, 14283370, 14337104,
+    14390298, 14442951, 14495059, 14546622, 14597637, 14648103, 14698017, 14747378,
+    14796184, 14844432, 14892122, 14939251, 14985817, 15031819, 15077256, 15122124,
+    15166424, 15210152, 15253308, 15295889, 15337895, 15379323, 15420172, 15460440,
+    15500126, 15539229, 15577747, 15615678, 15653022, 15689776, 15725939, 15761510,
+    15796488, 15830871, 15864658, 15897848, 15930439, 15962431, 15993821, 16024610,
+    16054795, 16084375, 16113350, 16141719, 16169479, 16196631, 16223173, 16249104,
+    16274424, 16299131, 16323224, 16346702, 16369565, 16391812, 16413442, 16434454,
+    16454846, 16474620, 16493773, 16512305, 16530216, 16547504, 16564169, 16580211,
+    16595628, 16610420, 16624588, 16638129, 16651044, 16663331, 16674992, 16686025,
+    16696429, 16706205, 16715352, 16723869, 16731757, 16739015, 16745643, 16751640,
+    16757007, 16761743, 16765847, 16769321, 16772163, 16774374, 16775953, 16776900,
+    16777216,
+];
+
+/// `atan` of each 1/256th between 0 and 1, with 24 fractional bits
+const ATAN_TABLE: [i64; 257] = [
+    0, 65536, 131069, 196599, 262123, 327638, 393144, 458638,
+    524117, 589581, 655027, 720453, 785857, 851237, 916591, 981917,
+    1047214, 1112479, 1177710, 1242905, 1308063, 1373181, 1438258, 1503292,
+    1568280, 1633221, 1698113, 1762954, 1827743, 1892476, 1957153, 2021772,
+    2086331, 2150827, 2215260, 2279627, 2343926, 2408156, 2472315, 2536402,
+    2600414, 2664350, 2728207, 2791985, 2855682, 2919296, 2982825, 3046268,
+    3109623, 3172888, 3236062, 3299143, 3362130, 3425021, 3487814, 3550509,
+    3613103, 3675595, 3737983, 3800267, 3862444, 3924513, 3986473, 4048323,
+    4110060, 4171684, 4233193, 4294587, 4355862, 4417020, 4478057, 4538973,
+    4599767, 4660437, 4720982, 4781401, 4841693, 4901856, 4961890, 5021793,
+    5081565, 5141204, 5200709, 5260079, 5319313, 5378410, 5437369, 5496190,
+    5554870, 5613410, 5671808, 5730064, 5788176, 5846144, 5903966, 5961643,
+    6019173, 6076555, 6133789, 6190874, 6247809, 6304594, 6361227, 6417708,
+    6474037, 6530212, 6586234, 6642101, 6697813, 6753369, 6808770, 6864013,
+    6919099, 6974027, 7028797, 7083409, 7137860, 7192153, 7246285, 7300256,
+    7354067, 7407716, 7461204, 7514530, 7567693, 7620694, 7673531, 7726205,
+    7778716, 7831063, 7883246, 7935264, 7987118, 8038807, 8090331, 8141690,
+    8192884, 8243913, 8294775, 8345472, 8396004, 8446369, 8496568, 8546602,
+    8596469, 8646170, 8695704, 8745073, 8794275, 8843311, 8892180, 8940884,
+    8989421, 9037792, 9085997, 9134035, 9181908, 9229615, 9277156, 9324532,
+    9371741, 9418786, 9465665, 9512378, 9558927, 9605311, 9651530, 9697585,
+    9743475, 9789201, 9834763, 9880161, 9925396, 9970468, 10015376, 10060122,
+    10104705, 10149126, 10193384, 10237481, 10281417, 10325191, 10368805, 10412257,
+    10455550, 10498682, 10541655, 10584468, 10627123, 10669619, 10711956, 10754135,
+    10796157, 10838022, 10879729, 10921280, 10962675, 11003914, 11044997, 11085926,
+    11126700, 11167320, 11207785, 11248098, 11288257, 11328264, 11368118, 11407821,
+    11447372, 11486773, 11526023, 11565122, 11604073, 11642874, 11681526, 11720030,
+    11758386, 11796594, 11834656, 11872571, 11910341, 11947964, 11985443, 12022777,
+    12059966, 12097012, 12133915, 12170675, 12207292, 12243768, 12280103, 12316297,
+    12352350, 12388263, 12424037, 12459673, 12495169, 12530528, 12565749, 12600834,
+    12635782, 12670594, 12705270, 12739812, 12774219, 12808492, 12842631, 12876638,
+    12910512, 12944254, 12977865, 13011344, 13044693, 13077912, 13111002, 13143963,
+    13176795,
+];
+
+/// move `x` from `from` fractional bits to `to` fractional bits,
+/// rounding down if bits are lost
+fn rescale(x: i128, from: u32, to: u32) -> i128 {
+    if to >= from { x << (to - from) } else { x >> (from - to) }
+}
+
+/// move `x` from `from` fractional bits to `to` fractional bits,
+/// rounding to the nearest if bits are lost
+fn rescale_round(x: i128, from: u32, to: u32) -> i128 {
+    if to >= from {
+        x << (to - from)
+    } else {
+        (x + (1 << (from - to - 1))) >> (from - to)
+    }
+}
+
+/// A fixed point number stored in `I` with `F` fractional bits. The
+/// maths only uses integers so it gives the same answer everywhere.
+/// The operators saturate at the smallest and largest value, the
+/// `checked_` and `wrapping_` methods give the other behaviours.
+#[derive(Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Fixed<I, F>(pub I, PhantomData<F>);
+
+impl<I: Int, F: Frac> Fixed<I, F> {
+    /// create from the raw bits
+    pub fn from_bits(bits: I) -> Fixed<I, F> {
+        Fixed(bits, PhantomData)
+    }
+
+    pub fn min_value() -> Fixed<I, F> {
+        Fixed::from_bits(I::min_value())
+    }
+
+    pub fn max_value() -> Fixed<I, F> {
+        Fixed::from_bits(I::max_value())
+    }
+
+    pub fn pi() -> Fixed<I, F> {
+        Fixed::saturate(rescale_round(PI_60, 60, F::bits()))
+    }
+
+    pub fn frac_pi_2() -> Fixed<I, F> {
+        Fixed::saturate(rescale_round(FRAC_PI_2_60, 60, F::bits()))
+    }
+
+    pub fn tau() -> Fixed<I, F> {
+        Fixed::saturate(rescale_round(TAU_60, 60, F::bits()))
+    }
+
+    fn bits(self) -> i128 {
+        self.0.to_i128()
+    }
+
+    fn one() -> i128 {
+        1 << F::bits()
+    }
+
+    fn saturate(x: i128) -> Fixed<I, F> {
+        if x > I::max_value().to_i128() {
+            Fixed::max_value()
+        } else if x < I::min_value().to_i128() {
+            Fixed::min_value()
+        } else {
+            Fixed::from_bits(I::wrap(x))
+        }
+    }
+
+    fn checked(x: i128) -> Option<Fixed<I, F>> {
+        if x > I::max_value().to_i128() || x < I::min_value().to_i128() {
+            None
+        } else {
+            Some(Fixed::from_bits(I::wrap(x)))
+        }
+    }
+
+    fn wrap(x: i128) -> Fixed<I, F> {
+        Fixed::from_bits(I::wrap(x))
+    }
+
+    /// get the value without the fractional bits
+    pub fn trunc(self) -> Fixed<I, F> {
+        Fixed::wrap(self.bits() & !(Fixed::<I, F>::one() - 1))
+    }
+
+    /// get the fractional bits
+    pub fn fract(self) -> Fixed<I, F> {
+        Fixed::wrap(self.bits() & (Fixed::<I, F>::one() - 1))
+    }
+
+    /// the absolute value, the smallest value saturates to the largest
+    pub fn abs(self) -> Fixed<I, F> {
+        Fixed::saturate(self.bits().abs())
+    }
+
+    pub fn checked_add(self, rhs: Fixed<I, F>) -> Option<Fixed<I, F>> {
+        Fixed::checked(self.bits() + rhs.bits())
+    }
+
+    pub fn checked_sub(self, rhs: Fixed<I, F>) -> Option<Fixed<I, F>> {
+        Fixed::checked(self.bits() - rhs.bits())
+    }
+
+    pub fn checked_mul(self, rhs: Fixed<I, F>) -> Option<Fixed<I, F>> {
+        Fixed::checked((self.bits() * rhs.bits()) >> F::bits())
+    }
+
+    /// divide, returning `None` when dividing by zero or if the
+    /// result does not fit
+    pub fn checked_div(self, rhs: Fixed<I, F>) -> Option<Fixed<I, F>> {
+        if rhs.bits() == 0 {
+            return None;
+        }
+        Fixed::checked((self.bits() << F::bits()) / rhs.bits())
+    }
+
+    pub fn wrapping_add(self, rhs: Fixed<I, F>) -> Fixed<I, F> {
+        Fixed::wrap(self.bits() + rhs.bits())
+    }
+
+    pub fn wrapping_sub(self, rhs: Fixed<I, F>) -> Fixed<I, F> {
+        Fixed::wrap(self.bits() - rhs.bits())
+    }
+
+    pub fn wrapping_mul(self, rhs: Fixed<I, F>) -> Fixed<I, F> {
+        Fixed::wrap((self.bits() * rhs.bits()) >> F::bits())
+    }
+
+    /// divide, wrapping if the result does not fit. Panics when
+    /// dividing by zero like the integer version
+    pub fn wrapping_div(self, rhs: Fixed<I, F>) -> Fixed<I, F> {
+        Fixed::wrap((self.bits() << F::bits()) / rhs.bits())
+    }
+
+    pub fn saturating_add(self, rhs: Fixed<I, F>) -> Fixed<I, F> {
+        Fixed::saturate(self.bits() + rhs.bits())
+    }
+
+    pub fn saturating_sub(self, rhs: Fixed<I, F>) -> Fixed<I, F> {
+        Fixed::saturate(self.bits() - rhs.bits())
+    }
+
+    pub fn saturating_mul(self, rhs: Fixed<I, F>) -> Fixed<I, F> {
+        Fixed::saturate((self.bits() * rhs.bits()) >> F::bits())
+    }
+
+    /// divide, stopping at the smallest or largest value if the result
+    /// does not fit. Dividing by zero gives the largest value with the
+    /// sign of `self`, or zero when `self` is zero
+    pub fn saturating_div(self, rhs: Fixed<I, F>) -> Fixed<I, F> {
+        match (self.bits().signum(), rhs.bits()) {
+            (0, 0) => Fixed::default(),
+            (1, 0) => Fixed::max_value(),
+            (_, 0) => Fixed::min_value(),
+            (_, rhs) => Fixed::saturate((self.bits() << F::bits()) / rhs),
+        }
+    }
+
+    /// the square root rounded down, negative values give zero
+    pub fn sqrt(self) -> Fixed<I, F> {
+        if self.bits() <= 0 {
+            return Fixed::from_bits(I::wrap(0));
+        }
+
+        // sqrt(x * 2^F) = sqrt(x) * 2^(F/2), so shifting the input up by
+        // another F bits leaves the root with F fractional bits
+        Fixed::saturate(isqrt((self.bits() as u128) << F::bits()) as i128)
+    }
+
+    /// `sqrt(self * self + other * other)` rounded down, without
+    /// overflowing when the squares would not fit
+    pub fn hypot(self, other: Fixed<I, F>) -> Fixed<I, F> {
+        let (a, b) = (self.bits().abs() as u128, other.bits().abs() as u128);
+        Fixed::saturate(isqrt(a * a + b * b) as i128)
+    }
+
+    /// the sine of an angle in radians, interpolated from a table so
+    /// it is accurate to about 24 fractional bits
+    pub fn sin(self) -> Fixed<I, F> {
+        let angle = rescale(self.bits(), F::bits(), TABLE_BITS);
+        Fixed::saturate(rescale_round(sin_turn(angle, 0), TABLE_BITS, F::bits()))
+    }
+
+    /// the cosine of an angle in radians, interpolated from a table so
+    /// it is accurate to about 24 fractional bits
+    pub fn cos(self) -> Fixed<I, F> {
+        let angle = rescale(self.bits(), F::bits(), TABLE_BITS);
+        Fixed::saturate(rescale_round(sin_turn(angle, 256), TABLE_BITS, F::bits()))
+    }
+
+    /// the angle in radians from the positive x axis to the point
+    /// `(x, y)`, between -PI and PI. Interpolated from a table
+    pub fn atan2(y: Fixed<I, F>, x: Fixed<I, F>) -> Fixed<I, F> {
+        let (ax, ay) = (x.bits().abs(), y.bits().abs());
+        if ax == 0 && ay == 0 {
+            return Fixed::from_bits(I::wrap(0));
+        }
+
+        // look up the angle of the smaller side over the larger
+        let (lo, hi) = if ay > ax { (ax, ay) } else { (ay, ax) };
+        let pos = ((lo << TABLE_BITS) / hi) * 256;
+        let (idx, frac) = ((pos >> TABLE_BITS) as usize, pos & ((1 << TABLE_BITS) - 1));
+        let mut angle = ATAN_TABLE[idx] as i128;
+        if idx < 256 {
+            angle += ((ATAN_TABLE[idx + 1] - ATAN_TABLE[idx]) as i128 * frac) >> TABLE_BITS;
+        }
+
+        if ay > ax {
+            angle = rescale_round(FRAC_PI_2_60, 60, TABLE_BITS) - angle;
+        }
+        if x.bits() < 0 {
+            angle = rescale_round(PI_60, 60, TABLE_BITS) - angle;
+        }
+        if y.bits() < 0 {
+            angle = -angle;
+        }
+        Fixed::saturate(rescale_round(angle, TABLE_BITS, F::bits()))
+    }
+}
+
+/// the integer square root of `n` rounded down
+fn isqrt(n: u128) -> u128 {
+    let (mut rem, mut root) = (n, 0u128);
+    let mut bit = 1u128 << 126;
+    while bit > n {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if rem >= root + bit {
+            rem -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+/// the sine of an angle in radians with `TABLE_BITS` fractional bits,
+/// `offset` moves the angle on by that many 1/1024ths of a turn
+fn sin_turn(angle: i128, offset: usize) -> i128 {
+    let tau = rescale_round(TAU_60, 60, TABLE_BITS);
+    let angle = ((angle % tau) + tau) % tau;
+
+    // split the turn into 1024 steps and the distance into the step
+    let pos = angle * 1024;
+    let (step, frac) = ((pos / tau) as usize + offset, pos % tau);
+    let i = step % 256;
+    let (a, b) = match step / 256 % 4 {
+        0 => (SIN_TABLE[i], SIN_TABLE[i + 1]),
+        1 => (SIN_TABLE[256 - i], SIN_TABLE[255 - i]),
+        2 => (-SIN_TABLE[i], -SIN_TABLE[i + 1]),
+        _ => (-SIN_TABLE[256 - i], -SIN_TABLE[255 - i]),
+    };
+    a as i128 + (b - a) as i128 * frac / tau
+}
+
+impl<I: Int, F: Frac> Default for Fixed<I, F> {
+    fn default() -> Fixed<I, F> {
+        Fixed::from_bits(I::wrap(0))
+    }
+}
+
+impl<I: Int, F: Frac> Neg for Fixed<I, F> {
+    type Output = Fixed<I, F>;
+    fn neg(self) -> Fixed<I, F> {
+        Fixed::saturate(-self.bits())
+    }
+}
+
+impl<I: Int, F: Frac> Add for Fixed<I, F> {
+    type Output = Fixed<I, F>;
+    fn add(self, rhs: Fixed<I, F>) -> Fixed<I, F> {
+        self.saturating_add(rhs)
+    }
+}
+
+impl<I: Int, F: Frac> Sub for Fixed<I, F> {
+    type Output = Fixed<I, F>;
+    fn sub(self, rhs: Fixed<I, F>) -> Fixed<I, F> {
+        self.saturating_sub(rhs)
+    }
+}
+
+/// saturates like `saturating_mul`, before `Fixed` a `Q8p24` that
+/// overflowed wrapped around instead
+impl<I: Int, F: Frac> Mul for Fixed<I, F> {
+    type Output = Fixed<I, F>;
+    fn mul(self, rhs: Fixed<I, F>) -> Fixed<I, F> {
+        self.saturating_mul(rhs)
+    }
+}
+
+/// saturates like `saturating_div`, so dividing by zero does not panic
+impl<I: Int, F: Frac> Div for Fixed<I, F> {
+    type Output = Fixed<I, F>;
+    fn div(self, rhs: Fixed<I, F>) -> Fixed<I, F> {
+        self.saturating_div(rhs)
+    }
+}
+
+impl<I: Int, F: Frac> AddAssign for Fixed<I, F> {
+    fn add_assign(&mut self, rhs: Fixed<I, F>) {
+        *self = *self + rhs;
+    }
+}
+
+impl<I: Int, F: Frac> SubAssign for Fixed<I, F> {
+    fn sub_assign(&mut self, rhs: Fixed<I, F>) {
+        *self = *self - rhs;
+    }
+}
+
+impl<I: Int, F: Frac> MulAssign for Fixed<I, F> {
+    fn mul_assign(&mut self, rhs: Fixed<I, F>) {
+        *self = *self * rhs;
+    }
+}
+
+impl<I: Int, F: Frac> DivAssign for Fixed<I, F> {
+    fn div_assign(&mut self, rhs: Fixed<I, F>) {
+        *self = *self / rhs;
+    }
+}
+
+/// the fractional part of a number written out to `n` decimal places,
+/// rounded to the nearest. Returns the digits and if rounding carried
+/// into the integer part
+fn decimal_digits(frac: u128, frac_bits: u32, n: usize) -> (Vec<u8>, bool) {
+    let mask = (1u128 << frac_bits) - 1;
+    let mut digits = vec![];
+    let mut rem = frac;
+    for _ in 0..n {
+        rem *= 10;
+        digits.push((rem >> frac_bits) as u8);
+        rem &= mask;
+    }
+
+    if rem << 1 >> frac_bits != 0 {
+        for d in digits.iter_mut().rev() {
+            if *d == 9 {
+                *d = 0;
+            } else {
+                *d += 1;
+                return (digits, false);
+            }
+        }
+        return (digits, true);
+    }
+    (digits, false)
+}
+
+/// turn decimal digits after the point into fractional bits, rounded
+/// to the nearest. Digits past the 24th are ignored
+fn parse_digits(digits: &[u8], frac_bits: u32) -> u128 {
+    let (mut num, mut den) = (0u128, 1u128);
+    for &d in digits.iter().take(24) {
+        num = num * 10 + d as u128;
+        den *= 10;
+    }
+    ((num << frac_bits) + den / 2) / den
+}
+
+/// prints the shortest decimal that parses back to the same value, or
+/// rounds to the nearest if a precision is given
+impl<I: Int, F: Frac> Display for Fixed<I, F> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let bits = self.bits();
+        let mag = bits.abs() as u128;
+        let mut int = mag >> F::bits();
+        let frac = mag & ((1 << F::bits()) - 1);
+
+        let digits = match f.precision() {
+            Some(n) => {
+                let (digits, carry) = decimal_digits(frac, F::bits(), n);
+                if carry {
+                    int += 1;
+                }
+                digits
+            }
+            None => {
+                (0..).map(|n| decimal_digits(frac, F::bits(), n))
+                    .find(|&(ref digits, carry)| !carry && parse_digits(digits, F::bits()) == frac)
+                    .unwrap()
+                    .0
+            }
+        };
+
+        let mut s = int.to_string();
+        if !digits.is_empty() {
+            s.push('.');
+            s.extend(digits.iter().map(|&d| (b'0' + d) as char));
+        }
+        f.pad_integral(bits >= 0, "", &s)
+    }
+}
+
+/// prints the value as an f64 like `Q8p24` always has, use `Display`
+/// for the exact decimal
+impl<I: Int, F: Frac> Debug for Fixed<I, F> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let x: f64 = (*self).into();
+        write!(f, "{:?}", x)
+    }
+}
+
+/// the error returned when a string is not a decimal number
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseFixedError;
+
+impl Display for ParseFixedError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("invalid fixed point number")
+    }
+}
+
+impl Error for ParseFixedError {
+    fn description(&self) -> &str {
+        "invalid fixed point number"
+    }
+}
+
+/// parses a decimal number like `-12.375`, values out of range saturate
+impl<I: Int, F: Frac> FromStr for Fixed<I, F> {
+    type Err = ParseFixedError;
+    fn from_str(s: &str) -> Result<Fixed<I, F>, ParseFixedError> {
+        let (neg, s) = match s.chars().next() {
+            Some('-') => (true, &s[1..]),
+            Some('+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (int, frac) = match s.find('.') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => (s, ""),
+        };
+
+        let is_digits = |s: &str| s.bytes().all(|b| b'0' <= b && b <= b'9');
+        if int.len() + frac.len() == 0 || !is_digits(int) || !is_digits(frac) {
+            return Err(ParseFixedError);
+        }
+
+        // anything with this many digits is out of range anyway
+        let mut value: i128 = 0;
+        for b in int.bytes() {
+            value = std::cmp::min(value * 10 + (b - b'0') as i128, 1 << 80);
+        }
+        let digits: Vec<u8> = frac.bytes().map(|b| b - b'0').collect();
+        value = (value << F::bits()) + parse_digits(&digits, F::bits()) as i128;
+        Ok(Fixed::saturate(if neg { -value } else { value }))
+    }
+}
+
+/// values past the integer range saturate, the largest integer is
+/// treated as out of range
+impl<I: Int, F: Frac> From<f64> for Fixed<I, F> {
+    fn from(f: f64) -> Fixed<I, F> {
+        let top = 2f64.powi((I::bits() - F::bits()) as i32 - 1);
+        if f > top - 1. {
+            Fixed::max_value()
+        } else if f < -top {
+            Fixed::min_value()
+        } else {
+            let x = f * 2f64.powi(F::bits() as i32);
+            Fixed::saturate(x.round() as i128)
+        }
+    }
+}
+
+impl<I: Int, F: Frac> From<f32> for Fixed<I, F> {
+    fn from(f: f32) -> Fixed<I, F> {
+        Fixed::from(f as f64)
+    }
+}
+
+impl<I: Int, F: Frac> From<i8> for Fixed<I, F> {
+    fn from(f: i8) -> Fixed<I, F> {
+        Fixed::saturate((f as i128) << F::bits())
+    }
+}
+
+impl<I: Int, F: Frac> From<Fixed<I, F>> for f64 {
+    fn from(f: Fixed<I, F>) -> f64 {
+        f.bits() as f64 / 2f64.powi(F::bits() as i32)
+    }
+}
+
+impl<I: Int, F: Frac> From<Fixed<I, F>> for f32 {
+    fn from(f: Fixed<I, F>) -> f32 {
+        f64::from(f) as f32
+    }
+}
+
+impl From<Q8p24> for i8 {
+    fn from(f: Q8p24) -> i8 {
+        (f.0 >> 24) as i8
+    }
+}
+
+/// conversions to a type with at least as many integer and fractional
+/// bits, these never lose anything
+macro_rules! widen {
+    ($i:ty, $f:ty => $j:ty, $g:ty) => {
+        impl From<Fixed<$i, $f>> for Fixed<$j, $g> {
+            fn from(f: Fixed<$i, $f>) -> Fixed<$j, $g> {
+                Fixed::wrap(rescale(f.bits(), <$f as Frac>::bits(), <$g as Frac>::bits()))
+            }
+        }
+    }
+}
+
+widen!(i32, F24 => i64, F32);
+widen!(i32, F16 => i64, F32);
+
+#[cfg(test)]
+mod test {
+    use std;
+    use quickcheck::{QuickCheck, Testable};
+    use super::{Fixed, Int, Frac, Q8p24, Q16p16, Q32p32};
+
+    fn quickcheck<A: Testable>(a: A) {
+        QuickCheck::new().tests(100).quickcheck(a)
+    }
+
+
+    fn apx_eq(a: f64, b: f64) -> bool {
+        let delta = a - b;
+        delta < (1. / (1 << 18) as f64)
+    }
+
+    #[test]
+    fn into() {
+        fn into(x: f64) -> bool {
+            if x > 127. || x < -127. {
+                return true;
+            }
+
+            let y: Q8p24 = x.into();
+            let z: f64 = y.into();
+            apx_eq(x, z)
+        }
+
+        quickcheck(into as fn(f64) -> bool);
+
+        for i in -127..128 {
+            into(i as f64);
+        }
+    }
+
+    #[test]
+    fn add() {
+        fn add(x: f64, y: f64) -> bool {
+            let (xi, yi): (Q8p24, Q8p24) = (x.into(), y.into());
+            let (x, y): (f64, f64) = (x.into(), y.into());
+            let zy = xi + yi;
+            let z = x + y;
+            if z.abs() >= 127. {
+                return true;
+            }
+            apx_eq(zy.into(), z)
+        }
+
+        quickcheck(add as fn(f64, f64) -> bool);
+    }
+
+    #[test]
+    fn sub() {
+        fn sub(x: f64, y: f64) -> bool {
+            let (xi, yi): (Q8p24, Q8p24) = (x.into(), y.into());
+            let zy = xi - yi;
+            let z = x - y;
+            if z.abs() >= 127. {
+                return true;
+            }
+            apx_eq(zy.into(), z)
+        }
+
+        quickcheck(sub as fn(f64, f64) -> bool);
+    }
+
+    #[test]
+    fn mul() {
+        fn mul(x: f64, y: f64) -> bool {
+            let (xi, yi): (Q8p24, Q8p24) = (x.into(), y.into());
+            let zi = xi * yi;
+            let z = x * y;
+            if z.abs() >= 127. {
+                return true;
+            }
+            apx_eq(zi.into(), z)
+        }
+
+        quickcheck(mul as fn(f64, f64) -> bool);
+    }
+
+    /// close enough for the table based functions
+    fn close<I: Int, F: Frac>(a: Fixed<I, F>, b: f64) -> bool {
+        let a: f64 = a.into();
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn neg() {
+        fn neg(x: f64) -> bool {
+            let xi: Q8p24 = x.into();
+            -xi == Q8p24::from_bits(0) - xi && -(-xi) == xi
+        }
+
+        quickcheck(neg as fn(f64) -> bool);
+        assert_eq!(Q8p24::max_value(), -Q8p24::min_value());
+        assert_eq!(Q8p24::max_value(), Q8p24::min_value().abs());
+    }
+
+    #[test]
+    fn abs() {
+        fn abs(x: f64) -> bool {
+            let xi: Q8p24 = x.into();
+            let (zi, z): (f64, f64) = (xi.abs().into(), f64::from(xi).abs());
+            zi == z
+        }
+
+        quickcheck(abs as fn(f64) -> bool);
+    }
+
+    #[test]
+    fn assign() {
+        fn assign(x: f64, y: f64) -> bool {
+            let (xi, yi): (Q8p24, Q8p24) = (x.into(), y.into());
+            let (mut a, mut b, mut c) = (xi, xi, xi);
+            a += yi;
+            b -= yi;
+            c *= yi;
+            let d = if yi != Q8p24::from_bits(0) {
+                let mut d = xi;
+                d /= yi;
+                d == xi / yi
+            } else {
+                true
+            };
+            a == xi + yi && b == xi - yi && c == xi * yi && d
+        }
+
+        quickcheck(assign as fn(f64, f64) -> bool);
+    }
+
+    #[test]
+    fn checked() {
+        fn checked(x: f64, y: f64) -> bool {
+            let (xi, yi): (Q8p24, Q8p24) = (x.into(), y.into());
+            let (x, y) = (xi.0 as i64, yi.0 as i64);
+            let fits = |z: i64| if z > std::i32::MAX as i64 || z < std::i32::MIN as i64 {
+                None
+            } else {
+                Some(Q8p24::from_bits(z as i32))
+            };
+
+            let div = if y == 0 { None } else { fits((x << 24) / y) };
+            xi.checked_add(yi) == fits(x + y) &&
+            xi.checked_sub(yi) == fits(x - y) &&
+            xi.checked_mul(yi) == fits((x * y) >> 24) &&
+            xi.checked_div(yi) == div
+        }
+
+        quickcheck(checked as fn(f64, f64) -> bool);
+        assert_eq!(None, Q8p24::max_value().checked_add(Q8p24::from_bits(1)));
+        assert_eq!(None, Q8p24::from(100i8).checked_mul(Q8p24::from(2i8)));
+        assert_eq!(None, Q8p24::from(1i8).checked_div(Q8p24::from_bits(0)));
+    }
+
+    #[test]
+    fn wrapping() {
+        fn wrapping(x: f64, y: f64) -> bool {
+            let (xi, yi): (Q8p24, Q8p24) = (x.into(), y.into());
+            let same = |a: Option<Q8p24>, b: Q8p24| a.map_or(true, |a| a == b);
+            xi.wrapping_add(yi) == Q8p24::from_bits(xi.0.wrapping_add(yi.0)) &&
+            xi.wrapping_sub(yi) == Q8p24::from_bits(xi.0.wrapping_sub(yi.0)) &&
+            same(xi.checked_mul(yi), xi.wrapping_mul(yi)) &&
+            (yi == Q8p24::from_bits(0) || same(xi.checked_div(yi), xi.wrapping_div(yi)))
+        }
+
+        quickcheck(wrapping as fn(f64, f64) -> bool);
+        assert_eq!(Q8p24::min_value(), Q8p24::max_value().wrapping_add(Q8p24::from_bits(1)));
+    }
+
+    #[test]
+    fn sqrt() {
+        fn sqrt(x: f64) -> bool {
+            let xi: Q8p24 = x.abs().into();
+            let root = xi.sqrt().0 as u64;
+            let n = (xi.0 as u64) << 24;
+            root * root <= n && n < (root + 1) * (root + 1)
+        }
+
+        quickcheck(sqrt as fn(f64) -> bool);
+        assert_eq!(Q8p24::from(3i8), Q8p24::from(9i8).sqrt());
+        assert_eq!(Q8p24::from(0.5), Q8p24::from(0.25).sqrt());
+        assert_eq!(Q8p24::from_bits(0), Q8p24::from(-4i8).sqrt());
+        assert!(close(Q8p24::max_value().sqrt(), 128f64.sqrt()));
+    }
+
+    #[test]
+    fn hypot() {
+        fn hypot(x: f64, y: f64) -> bool {
+            let (xi, yi): (Q8p24, Q8p24) = (x.into(), y.into());
+            let (a, b) = ((xi.0 as i64).abs() as u64, (yi.0 as i64).abs() as u64);
+            let n = a * a + b * b;
+            let root = xi.hypot(yi).0 as u64;
+            root == std::i32::MAX as u64 || (root * root <= n && n < (root + 1) * (root + 1))
+        }
+
+        quickcheck(hypot as fn(f64, f64) -> bool);
+        assert_eq!(Q8p24::from(5i8), Q8p24::from(-3i8).hypot(Q8p24::from(4i8)));
+        assert_eq!(Q8p24::from(100i8), Q8p24::from(60i8).hypot(Q8p24::from(-80i8)));
+        assert_eq!(Q8p24::max_value(), Q8p24::max_value().hypot(Q8p24::from(1i8)));
+    }
+
+    #[test]
+    fn sin_cos() {
+        fn sin_cos(x: f64) -> bool {
+            let xi: Q8p24 = x.into();
+            let x: f64 = xi.into();
+            close(xi.sin(), x.sin()) && close(xi.cos(), x.cos())
+        }
+
+        quickcheck(sin_cos as fn(f64) -> bool);
+        assert_eq!(Q8p24::from_bits(0), Q8p24::from_bits(0).sin());
+        assert_eq!(Q8p24::from(1i8), Q8p24::from_bits(0).cos());
+        assert!(close(Q8p24::frac_pi_2().sin(), 1.));
+        assert!(close(Q8p24::pi().cos(), -1.));
+        assert!(close(Q8p24::min_value().sin(), (-128f64).sin()));
+    }
+
+    #[test]
+    fn atan2() {
+        fn atan2(y: f64, x: f64) -> bool {
+            let (yi, xi): (Q8p24, Q8p24) = (y.into(), x.into());
+            let (y, x): (f64, f64) = (yi.into(), xi.into());
+            if x == 0. && y == 0. {
+                return Q8p24::atan2(yi, xi) == Q8p24::from_bits(0);
+            }
+            close(Q8p24::atan2(yi, xi), y.atan2(x))
+        }
+
+        quickcheck(atan2 as fn(f64, f64) -> bool);
+        for &(y, x) in &[(0i8, 1i8), (1, 0), (0, -1), (-1, 0), (1, 1), (-3, -3), (5, -2)] {
+            assert!(atan2(y as f64, x as f64));
+        }
+        assert_eq!(Q8p24::pi(), Q8p24::atan2(Q8p24::from_bits(0), Q8p24::from(-1i8)));
+    }
+
+    #[test]
+    fn display() {
+        fn display(x: f64) -> bool {
+            let xi: Q8p24 = x.into();
+            xi.to_string().parse::<Q8p24>() == Ok(xi)
+        }
+
+        quickcheck(display as fn(f64) -> bool);
+        assert_eq!("1.5", Q8p24::from(1.5).to_string());
+        assert_eq!("-0.25", Q8p24::from(-0.25).to_string());
+        assert_eq!("0.50", format!("{:.2}", Q8p24::from(0.5)));
+        assert_eq!(Ok(Q8p24::from(-2i8)), "-2".parse());
+        assert!("two".parse::<Q8p24>().is_err());
+    }
+
+    #[test]
+    fn widen() {
+        fn widen(x: f64, y: f64) -> bool {
+            let (xi, yi): (Q8p24, Q16p16) = (x.into(), y.into());
+            let (xw, yw): (Q32p32, Q32p32) = (xi.into(), yi.into());
+            f64::from(xi) == f64::from(xw) && f64::from(yi) == f64::from(yw)
+        }
+
+        quickcheck(widen as fn(f64, f64) -> bool);
+        assert_eq!(Q32p32::from(-128i8), Q8p24::min_value().into());
+        assert_eq!(Q32p32::from(-32768.), Q16p16::min_value().into());
+    }
+
+    #[test]
+    fn wide_range() {
+        fn wide_range(x: f64, y: f64) -> bool {
+            // spread the inputs out past what a Q8p24 can hold
+            let (x, y) = (x * 100., y * 100.);
+            let (xi, yi): (Q32p32, Q32p32) = (x.into(), y.into());
+            let (x, y): (f64, f64) = (xi.into(), yi.into());
+            let close = |a: Q32p32, b: f64| (f64::from(a) - b).abs() < 1e-6 * b.abs().max(1.);
+
+            close(xi + yi, x + y) && close(xi - yi, x - y) && close(xi * yi, x * y) &&
+            (y.abs() < 1. || close(xi / yi, x / y)) &&
+            close(xi.abs().sqrt(), x.abs().sqrt())
+        }
+
+        quickcheck(wide_range as fn(f64, f64) -> bool);
+        let big = Q16p16::from(30000.);
+        assert_eq!(Q16p16::from(20000.), big - Q16p16::from(10000.));
+        assert_eq!(Q16p16::max_value(), big + big);
+        assert!(close(Q16p16::atan2(Q16p16::from(1.), Q16p16::from(-1.)), 0.75 * std::f64::consts::PI));
+    }
+
+    #[test]
+    fn formats() {
+        fn formats(x: f64) -> bool {
+            let (a, b, c): (Q8p24, Q16p16, Q32p32) = (x.into(), (x * 100.).into(), (x * 1e6).into());
+            a.to_string().parse() == Ok(a) && b.to_string().parse() == Ok(b) &&
+            c.to_string().parse() == Ok(c)
+        }
+
+        quickcheck(formats as fn(f64) -> bool);
+        assert_eq!("0.1", Q32p32::from(0.1).to_string());
+        assert_eq!("-1234567.125", Q32p32::from(-1234567.125).to_string());
+        assert_eq!("1.000", format!("{:.3}", Q16p16::from(0.99999)));
+        assert_eq!("  -2.5", format!("{:6}", Q16p16::from(-2.5)));
+        assert_eq!(Ok(Q16p16::max_value()), "99999".parse());
+        for bad in &["", "-", ".", "1.2.3", "1e5", "0x10", " 1"] {
+            assert!(bad.parse::<Q16p16>().is_err());
+        }
+    }
+
+    /// the values most likely to go wrong, or `x` if `pick` is past them
+    fn edge(x: f64, pick: u8) -> Q8p24 {
+        match pick % 8 {
+            0 => Q8p24::min_value(),
+            1 => Q8p24::max_value(),
+            2 => Q8p24::from_bits(0),
+            3 => Q8p24::from_bits(1),
+            4 => Q8p24::from_bits(-1),
+            5 => Q8p24::from(-1i8),
+            _ => x.into(),
+        }
+    }
+
+    fn clamp(x: i64) -> Q8p24 {
+        Q8p24::from_bits(x.max(std::i32::MIN as i64).min(std::i32::MAX as i64) as i32)
+    }
+
+    #[test]
+    fn saturating() {
+        fn saturating(x: f64, px: u8, y: f64, py: u8) -> bool {
+            let (xi, yi) = (edge(x, px), edge(y, py));
+            let (a, b) = (xi.0 as i64, yi.0 as i64);
+
+            let div = match (a.signum(), b) {
+                (0, 0) => Q8p24::from_bits(0),
+                (1, 0) => Q8p24::max_value(),
+                (_, 0) => Q8p24::min_value(),
+                _ => clamp((a << 24) / b),
+            };
+            xi + yi == clamp(a + b) && xi - yi == clamp(a - b) &&
+            xi * yi == clamp((a * b) >> 24) && xi.saturating_mul(yi) == xi * yi &&
+            xi / yi == div && xi.saturating_div(yi) == div
+        }
+
+        quickcheck(saturating as fn(f64, u8, f64, u8) -> bool);
+        assert_eq!(Q8p24::max_value(), Q8p24::from(100i8) * Q8p24::from(2i8));
+        assert_eq!(Q8p24::min_value(), Q8p24::from(100i8) / Q8p24::from(-0.5));
+        assert_eq!(Q8p24::min_value(), Q8p24::min_value() / Q8p24::from_bits(1));
+        assert_eq!(Q8p24::max_value(), Q8p24::min_value() / Q8p24::from(-1i8));
+        assert_eq!(Q8p24::max_value(), Q8p24::from_bits(1) / Q8p24::from_bits(0));
+        assert_eq!(Q8p24::min_value(), Q8p24::from_bits(-1) / Q8p24::from_bits(0));
+        assert_eq!(Q8p24::from_bits(0), Q8p24::from_bits(0) / Q8p24::from_bits(0));
+    }
+
+    #[test]
+    fn q8p24_changes() {
+        // a Q8p24 that overflowed used to wrap, it now saturates and the
+        // old result is left to wrapping_mul
+        let (a, b) = (Q8p24::from(100i8), Q8p24::from(2i8));
+        assert_eq!(Q8p24::max_value(), a * b);
+        assert_eq!(Q8p24::min_value(), -a * b);
+        assert_eq!(Q8p24::from(-56i8), a.wrapping_mul(b));
+
+        // debug still prints it as an f64
+        assert_eq!("1.0", format!("{:?}", Q8p24::from(1i8)));
+        assert_eq!("-0.5", format!("{:?}", Q8p24::from(-0.5)));
+        assert_eq!("1", format!("{}", Q8p24::from(1i8)));
+    }
+
+    #[test]
+    fn div_agrees() {
+        fn div_agrees(x: f64, px: u8, y: f64, py: u8) -> bool {
+            let (xi, yi) = (edge(x, px), edge(y, py));
+            match xi.checked_div(yi) {
+                Some(z) => z == xi / yi && z == xi.wrapping_div(yi),
+                None if yi.0 == 0 => true,
+                // only overflow is left, which pushes away from zero
+                None => {
+                    let positive = (xi.0 < 0) == (yi.0 < 0);
+                    xi / yi == if positive { Q8p24::max_value() } else { Q8p24::min_value() }
+                }
+            }
+        }
+
+        quickcheck(div_agrees as fn(f64, u8, f64, u8) -> bool);
+        for px in 0..7 {
+            for py in 0..7 {
+                assert!(div_agrees(0.5, px, -3., py));
+            }
+        }
+    }
+}
@@ -1,10 +1,13 @@
-mod q8p24;
+use std;
+
+mod fixed;
 mod vector;
 
 use ecs;
 use ecs::Join;
-use rtree::Rectangle;
+use rtree::{Rectangle, Point};
 pub use self::vector::Vector;
+pub use self::fixed::{Fixed, Q8p24, Q16p16, Q32p32};
 use transform::{Transform, Location};
 use Step;
 use super::MovingTo;
@@ -15,42 +18,88 @@ impl ecs::Component for Movement {
 
 pub struct Movement {
     pub vector: Vector,
+    /// added to the vector every tick
+    pub acceleration: Vector,
+    /// the part of the vector lost every tick, from 0 which keeps it
+    /// all to 1 which stops dead. Values outside of that are clamped
+    pub friction: Q8p24,
+    /// the vector is clamped to this length after it is integrated
+    pub max_speed: Option<Q8p24>,
     remainder: Vector
 }
 
 impl Movement {
     /// create a new movement vector
     pub fn new<T>(x: T, y: T) -> Movement
-        where q8p24::Q8p24: From<T>
+        where Q8p24: From<T>
     {
         Movement {
             vector: (x, y).into(),
+            acceleration: (0i8, 0i8).into(),
+            friction: Q8p24::default(),
+            max_speed: None,
             remainder: (0i8, 0i8).into()
         }
     }
 
+    /// set the friction, it is clamped between 0 and 1
+    pub fn with_friction<T>(mut self, friction: T) -> Movement
+        where Q8p24: From<T>
+    {
+        self.friction = clamp_friction(friction.into());
+        self
+    }
+
+    /// set the max speed
+    pub fn with_max_speed<T>(mut self, max_speed: T) -> Movement
+        where Q8p24: From<T>
+    {
+        self.max_speed = Some(max_speed.into());
+        self
+    }
+
+    /// add a one off kick to the vector
+    pub fn impulse(&mut self, impulse: Vector) {
+        self.vector = self.vector + impulse;
+    }
+
+    /// step the vector forward a tick, this is done by `next`
+    fn integrate(&mut self) {
+        let keep = Q8p24::from(1i8) - clamp_friction(self.friction);
+        let v = self.vector + self.acceleration;
+        // round towards zero so a drifting entity does come to a stop
+        let damp = |x: Q8p24| if x < Q8p24::default() { -(-x * keep) } else { x * keep };
+        self.vector = Vector::new(damp(v.x), damp(v.y));
+
+        if let Some(max) = self.max_speed {
+            self.vector = self.vector.clamp_length(max);
+        }
+    }
+
     // take a rectangle and move it based on the remainder
-    pub fn next(&mut self, mut pos: Rectangle) -> Rectangle {
+    pub fn next(&mut self, pos: Rectangle) -> Rectangle {
+        self.integrate();
         let sum = self.remainder + self.vector;
         let rem = sum.trunc();
         self.remainder = sum.fract();
 
-        if self.vector.x == q8p24::Q8p24(0) {
+        if self.vector.x == Q8p24::default() {
             self.remainder.x = (0.).into();
         }
-        if self.vector.y == q8p24::Q8p24(0) {
+        if self.vector.y == Q8p24::default() {
             self.remainder.y = (0.).into();
         }
 
         let (x, y): (i8, i8) = (rem.x.into(), rem.y.into());
-        pos.min.x = pos.min.x + x as i16;
-        pos.min.y = pos.min.y + y as i16;
-        pos.max.x = pos.max.x + x as i16;
-        pos.max.y = pos.max.y + y as i16;
-        pos
+        pos.translate(Point::new(x as i16, y as i16))
     }
 }
 
+/// keep friction between 0 and 1, past that the vector would flip or grow
+fn clamp_friction(friction: Q8p24) -> Q8p24 {
+    std::cmp::max(Q8p24::default(), std::cmp::min(friction, Q8p24::from(1i8)))
+}
+
 pub struct System;
 
 impl ecs::System<Step> for System {
@@ -74,4 +123,82 @@ impl ecs::System<Step> for System {
             movto.insert(eid, MovingTo(mov.next(loc.0)));
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use rtree::{Rectangle, Point};
+    use super::{Movement, Vector, Q8p24};
+
+    fn rect() -> Rectangle {
+        Rectangle{
+            min: Point{x: 0, y: 0},
+            max: Point{x: 1, y: 1}
+        }
+    }
+
+    #[test]
+    fn constant() {
+        let mut mov = Movement::new(1i8, -2i8);
+        let mut pos = rect();
+        for _ in 0..10 {
+            pos = mov.next(pos);
+        }
+        assert_eq!(Point{x: 10, y: -20}, pos.min);
+        assert_eq!(Vector::from((1i8, -2i8)), mov.vector);
+    }
+
+    #[test]
+    fn friction() {
+        let mut mov = Movement::new(3., -3.).with_friction(0.25);
+        mov.next(rect());
+        assert_eq!(Vector::from((2.25, -2.25)), mov.vector);
+        for _ in 0..200 {
+            mov.next(rect());
+        }
+        assert_eq!(Vector::default(), mov.vector);
+    }
+
+    #[test]
+    fn friction_range() {
+        let mut mov = Movement::new(2i8, -2i8).with_friction(3i8);
+        assert_eq!(Q8p24::from(1i8), mov.friction);
+        mov.next(rect());
+        assert_eq!(Vector::default(), mov.vector);
+
+        let mut mov = Movement::new(2i8, -2i8).with_friction(-1i8);
+        assert_eq!(Q8p24::default(), mov.friction);
+        mov.friction = Q8p24::from(-1i8);
+        mov.next(rect());
+        assert_eq!(Vector::from((2i8, -2i8)), mov.vector);
+    }
+
+    #[test]
+    fn acceleration() {
+        // settles where the friction takes away what is added
+        let mut mov = Movement::new(0i8, 0i8).with_friction(0.5);
+        mov.acceleration = (1i8, -1i8).into();
+        for _ in 0..100 {
+            mov.next(rect());
+        }
+        let off = mov.vector - Vector::from((1i8, -1i8));
+        assert!(off.length() <= Q8p24::from_bits(2));
+
+        let mut mov = Movement::new(0i8, 0i8).with_max_speed(5i8);
+        mov.acceleration = (3i8, 4i8).into();
+        mov.next(rect());
+        assert_eq!(Vector::from((3i8, 4i8)), mov.vector);
+        mov.next(rect());
+        assert!(mov.vector.length() <= Q8p24::from(5i8));
+        assert!(mov.vector.length() > Q8p24::from(4.999));
+    }
+
+    #[test]
+    fn impulse() {
+        let mut mov = Movement::new(1i8, 0i8).with_friction(0.5);
+        mov.impulse((0i8, 4i8).into());
+        assert_eq!(Vector::from((1i8, 4i8)), mov.vector);
+        mov.next(rect());
+        assert_eq!(Vector::from((0.5, 2.)), mov.vector);
+    }
+}
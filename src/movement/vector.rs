@@ -1,7 +1,8 @@
+use std;
 use std::ops::*;
-use super::q8p24::Q8p24;
+use super::fixed::Q8p24;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
 pub struct Vector{
     pub x: Q8p24,
     pub y: Q8p24
@@ -31,6 +32,85 @@ impl Vector {
             y: self.y.fract(),
         }
     }
+
+    pub fn dot(self, rhs: Vector) -> Q8p24 {
+        self.x * rhs.x + self.y * rhs.y
+    }
+
+    /// the squared length, this saturates for vectors longer than
+    /// about 11 so prefer `length` for comparing long vectors
+    pub fn length_squared(self) -> Q8p24 {
+        self.dot(self)
+    }
+
+    /// the length, this only saturates if the length itself is too
+    /// large for a Q8p24
+    pub fn length(self) -> Q8p24 {
+        self.x.hypot(self.y)
+    }
+
+    /// a vector with a length of 1 pointing the same way, a vector
+    /// with no length stays as it is
+    pub fn normalize(self) -> Vector {
+        let big = std::cmp::max((self.x.0 as i64).abs(), (self.y.0 as i64).abs());
+        if big == 0 {
+            return self;
+        }
+
+        // the length is rounded to a whole bit, so short vectors are
+        // scaled up first to keep that rounding from bending the result
+        let mut v = self;
+        let mut shift = 0;
+        while big << shift < 1 << 22 {
+            shift += 1;
+        }
+        if shift > 0 {
+            v = Vector::new(Q8p24::from_bits(v.x.0 << shift), Q8p24::from_bits(v.y.0 << shift));
+        }
+
+        // halve vectors that are too long to measure, this only loses
+        // the lowest bit which is far below what the result can hold
+        let mut len = v.length();
+        if len == Q8p24::max_value() {
+            v = v / Q8p24::from(2i8);
+            len = v.length();
+        }
+        v / len
+    }
+
+    /// rotate counter clockwise by `angle` radians
+    pub fn rotate(self, angle: Q8p24) -> Vector {
+        let (sin, cos) = (angle.sin(), angle.cos());
+        Vector {
+            x: self.x * cos - self.y * sin,
+            y: self.x * sin + self.y * cos,
+        }
+    }
+
+    /// the vector rotated a quarter turn counter clockwise
+    pub fn perp(self) -> Vector {
+        Vector {
+            x: -self.y,
+            y: self.x,
+        }
+    }
+
+    /// shorten the vector to `max` if it is longer, a `max` of zero or
+    /// less gives a vector with no length
+    pub fn clamp_length(self, max: Q8p24) -> Vector {
+        if max <= Q8p24::default() {
+            return Vector::default();
+        }
+        if self.length() <= max {
+            return self;
+        }
+        self.normalize() * max
+    }
+
+    /// the point `t` of the way from `self` to `rhs`
+    pub fn lerp(self, rhs: Vector, t: Q8p24) -> Vector {
+        self + (rhs - self) * t
+    }
 }
 
 impl Add for Vector {
@@ -53,6 +133,36 @@ impl Sub for Vector {
     }
 }
 
+impl Neg for Vector {
+    type Output = Vector;
+    fn neg(self) -> Vector {
+        Vector{
+            x: -self.x,
+            y: -self.y
+        }
+    }
+}
+
+impl Mul<Q8p24> for Vector {
+    type Output = Vector;
+    fn mul(self, rhs: Q8p24) -> Vector {
+        Vector{
+            x: self.x * rhs,
+            y: self.y * rhs
+        }
+    }
+}
+
+impl Div<Q8p24> for Vector {
+    type Output = Vector;
+    fn div(self, rhs: Q8p24) -> Vector {
+        Vector{
+            x: self.x / rhs,
+            y: self.y / rhs
+        }
+    }
+}
+
 impl<T> From<(T, T)> for Vector
     where Q8p24: From<T>
 {
@@ -60,3 +170,137 @@ impl<T> From<(T, T)> for Vector
         Vector::new(x.into(), y.into())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use quickcheck::{QuickCheck, Testable};
+    use super::Vector;
+    use super::super::fixed::Q8p24;
+
+    fn quickcheck<A: Testable>(a: A) {
+        QuickCheck::new().tests(100).quickcheck(a)
+    }
+
+    fn close(a: Q8p24, b: f64) -> bool {
+        let a: f64 = a.into();
+        (a - b).abs() < 1e-5 * b.abs().max(1.)
+    }
+
+    /// keep the components small enough that products do not saturate
+    fn small(x: f64, y: f64) -> Vector {
+        (x % 8., y % 8.).into()
+    }
+
+    #[test]
+    fn dot() {
+        fn dot(ax: f64, ay: f64, bx: f64, by: f64) -> bool {
+            let (a, b) = (small(ax, ay), small(bx, by));
+            let (ax, ay, bx, by): (f64, f64, f64, f64) = (a.x.into(), a.y.into(), b.x.into(), b.y.into());
+            close(a.dot(b), ax * bx + ay * by) && close(a.length_squared(), ax * ax + ay * ay) &&
+            a.dot(a.perp()).abs() <= Q8p24::from_bits(1)
+        }
+
+        quickcheck(dot as fn(f64, f64, f64, f64) -> bool);
+    }
+
+    #[test]
+    fn length() {
+        fn length(x: f64, y: f64) -> bool {
+            let v: Vector = (x, y).into();
+            let (x, y): (f64, f64) = (v.x.into(), v.y.into());
+            let n = v.normalize();
+            let unit = v == Vector::default() || close(n.length(), 1.);
+            (x.hypot(y) >= 127. || close(v.length(), x.hypot(y))) && unit
+        }
+
+        quickcheck(length as fn(f64, f64) -> bool);
+        let v: Vector = (80i8, -80i8).into();
+        assert!(close(v.length(), 80. * 2f64.sqrt()));
+        let v: Vector = (-128i8, -128i8).into();
+        assert_eq!(Q8p24::max_value(), v.length());
+        assert!(close(v.normalize().x, -0.5f64.sqrt()));
+        let n = Vector::from((3i8, -4i8)).normalize();
+        assert!(close(n.x, 0.6) && close(n.y, -0.8));
+        assert_eq!(Vector::default(), Vector::default().normalize());
+    }
+
+    #[test]
+    fn normalize_tiny() {
+        fn normalize_tiny(x: i32, y: i32) -> bool {
+            let v = Vector::new(Q8p24::from_bits(x % 1024), Q8p24::from_bits(y % 1024));
+            v == Vector::default() || close(v.normalize().length(), 1.)
+        }
+
+        quickcheck(normalize_tiny as fn(i32, i32) -> bool);
+        let bit = Q8p24::from_bits(1);
+        for &(x, y) in &[(bit, bit), (bit, bit + bit), (-bit, Q8p24::default())] {
+            assert!(close(Vector::new(x, y).normalize().length(), 1.));
+        }
+        assert!(close(Vector::from((1e-6, 1e-6)).normalize().length(), 1.));
+    }
+
+    #[test]
+    fn scale() {
+        fn scale(x: f64, y: f64, s: f64) -> bool {
+            let (v, s): (Vector, Q8p24) = (small(x, y), (s % 8.).into());
+            let zero = s == Q8p24::default();
+            v * s == Vector::new(v.x * s, v.y * s) && (zero || v / s == Vector::new(v.x / s, v.y / s))
+        }
+
+        quickcheck(scale as fn(f64, f64, f64) -> bool);
+    }
+
+    #[test]
+    fn rotate() {
+        fn rotate(x: f64, y: f64, angle: f64) -> bool {
+            let (v, angle): (Vector, Q8p24) = (small(x, y), angle.into());
+            let r = v.rotate(angle);
+            let (x, y, angle): (f64, f64, f64) = (v.x.into(), v.y.into(), angle.into());
+            let near = |a: Q8p24, b: f64| (f64::from(a) - b).abs() < 1e-4;
+            near(r.x, x * angle.cos() - y * angle.sin()) && near(r.y, x * angle.sin() + y * angle.cos())
+        }
+
+        quickcheck(rotate as fn(f64, f64, f64) -> bool);
+        let v: Vector = (2i8, 1i8).into();
+        assert_eq!(v.perp(), Vector::from((-1i8, 2i8)));
+        assert_eq!(-v, v.perp().perp());
+        let r = v.rotate(Q8p24::frac_pi_2());
+        assert!(close(r.x, -1.) && close(r.y, 2.));
+    }
+
+    #[test]
+    fn clamp_length() {
+        fn clamp_length(x: f64, y: f64, max: f64) -> bool {
+            let (v, max): (Vector, Q8p24) = ((x, y).into(), (max % 16.).into());
+            let c = v.clamp_length(max);
+            if max <= Q8p24::default() {
+                c == Vector::default()
+            } else if v.length() <= max {
+                c == v
+            } else {
+                // rounding may leave it a hair over
+                c.length() <= max + Q8p24::from_bits(64) && (v == Vector::default() || c.dot(v) >= Q8p24::default())
+            }
+        }
+
+        quickcheck(clamp_length as fn(f64, f64, f64) -> bool);
+        let v: Vector = (30i8, 40i8).into();
+        let c = v.clamp_length(Q8p24::from(5i8));
+        assert!(close(c.x, 3.) && close(c.y, 4.));
+        assert_eq!(Vector::default(), v.clamp_length(Q8p24::from(-5i8)));
+    }
+
+    #[test]
+    fn lerp() {
+        fn lerp(ax: f64, ay: f64, bx: f64, by: f64) -> bool {
+            let (a, b) = (small(ax, ay), small(bx, by));
+            let zero = a.lerp(b, Q8p24::default());
+            let one = a.lerp(b, Q8p24::from(1i8));
+            let half = a.lerp(b, Q8p24::from(0.5));
+            let mid = |a: Q8p24, b: Q8p24| (f64::from(a) + f64::from(b)) / 2.;
+            zero == a && one == b && close(half.x, mid(a.x, b.x)) && close(half.y, mid(a.y, b.y))
+        }
+
+        quickcheck(lerp as fn(f64, f64, f64, f64) -> bool);
+    }
+}
@@ -0,0 +1,287 @@
+use std;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// A type that can be used for the coordinates of a `Point`
+pub trait Coordinate: Copy + PartialEq + PartialOrd + Debug {
+    /// the integer morton and hilbert keys are stored in, it has
+    /// room for the bits of two coordinates
+    type Key: Copy + Ord + Hash + Debug + Send + Into<u64>;
+
+    /// the number of bits used by `to_bits`
+    fn bits() -> u32;
+
+    fn zero() -> Self;
+    fn min_value() -> Self;
+    fn max_value() -> Self;
+
+    /// add without overflowing, integers stop at their min or max value
+    fn saturating_add(self, b: Self) -> Self;
+
+    /// subtract without overflowing, integers stop at their min or max value
+    fn saturating_sub(self, b: Self) -> Self;
+
+    /// half of the coordinate, integers round towards zero
+    fn half(self) -> Self;
+
+    /// map the coordinate onto an unsigned integer that sorts
+    /// in the same order
+    fn to_bits(self) -> u32;
+
+    /// the reverse of `to_bits`
+    fn from_bits(bits: u32) -> Self;
+
+    /// narrow a key built from this coordinate's bits
+    fn key(key: u64) -> Self::Key;
+
+    fn to_f64(self) -> f64;
+
+    /// convert from a f64, rounding and saturating to what
+    /// the coordinate can hold
+    fn from_f64(f: f64) -> Self;
+
+    /// the coordinate as an integer if it is one, so that it can be
+    /// worked with exactly
+    fn to_i64(self) -> Option<i64>;
+}
+
+impl Coordinate for i16 {
+    type Key = u32;
+
+    fn bits() -> u32 {
+        16
+    }
+
+    fn zero() -> i16 {
+        0
+    }
+
+    fn min_value() -> i16 {
+        std::i16::MIN
+    }
+
+    fn max_value() -> i16 {
+        std::i16::MAX
+    }
+
+    fn saturating_add(self, b: i16) -> i16 {
+        i16::saturating_add(self, b)
+    }
+
+    fn saturating_sub(self, b: i16) -> i16 {
+        i16::saturating_sub(self, b)
+    }
+
+    fn half(self) -> i16 {
+        self / 2
+    }
+
+    fn to_bits(self) -> u32 {
+        (self as u16 ^ 0x8000) as u32
+    }
+
+    fn from_bits(bits: u32) -> i16 {
+        (bits as u16 ^ 0x8000) as i16
+    }
+
+    fn key(key: u64) -> u32 {
+        key as u32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(f: f64) -> i16 {
+        if f >= std::i16::MAX as f64 {
+            std::i16::MAX
+        } else if f <= std::i16::MIN as f64 {
+            std::i16::MIN
+        } else {
+            f.round() as i16
+        }
+    }
+
+    fn to_i64(self) -> Option<i64> {
+        Some(self as i64)
+    }
+}
+
+impl Coordinate for i32 {
+    type Key = u64;
+
+    fn bits() -> u32 {
+        32
+    }
+
+    fn zero() -> i32 {
+        0
+    }
+
+    fn min_value() -> i32 {
+        std::i32::MIN
+    }
+
+    fn max_value() -> i32 {
+        std::i32::MAX
+    }
+
+    fn saturating_add(self, b: i32) -> i32 {
+        i32::saturating_add(self, b)
+    }
+
+    fn saturating_sub(self, b: i32) -> i32 {
+        i32::saturating_sub(self, b)
+    }
+
+    fn half(self) -> i32 {
+        self / 2
+    }
+
+    fn to_bits(self) -> u32 {
+        self as u32 ^ 0x8000_0000
+    }
+
+    fn from_bits(bits: u32) -> i32 {
+        (bits ^ 0x8000_0000) as i32
+    }
+
+    fn key(key: u64) -> u64 {
+        key
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(f: f64) -> i32 {
+        if f >= std::i32::MAX as f64 {
+            std::i32::MAX
+        } else if f <= std::i32::MIN as f64 {
+            std::i32::MIN
+        } else {
+            f.round() as i32
+        }
+    }
+
+    fn to_i64(self) -> Option<i64> {
+        Some(self as i64)
+    }
+}
+
+impl Coordinate for f32 {
+    type Key = u64;
+
+    fn bits() -> u32 {
+        32
+    }
+
+    fn zero() -> f32 {
+        0.
+    }
+
+    fn min_value() -> f32 {
+        std::f32::NEG_INFINITY
+    }
+
+    fn max_value() -> f32 {
+        std::f32::INFINITY
+    }
+
+    fn saturating_add(self, b: f32) -> f32 {
+        self + b
+    }
+
+    fn saturating_sub(self, b: f32) -> f32 {
+        self - b
+    }
+
+    fn half(self) -> f32 {
+        self / 2.
+    }
+
+    /// positive floats sort like integers once the sign bit is set,
+    /// negative floats sort backwards so all of their bits are flipped
+    fn to_bits(self) -> u32 {
+        let bits = f32::to_bits(self);
+        if bits & 0x8000_0000 != 0 {
+            !bits
+        } else {
+            bits | 0x8000_0000
+        }
+    }
+
+    fn from_bits(bits: u32) -> f32 {
+        if bits & 0x8000_0000 != 0 {
+            f32::from_bits(bits & 0x7fff_ffff)
+        } else {
+            f32::from_bits(!bits)
+        }
+    }
+
+    fn key(key: u64) -> u64 {
+        key
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(f: f64) -> f32 {
+        f as f32
+    }
+
+    fn to_i64(self) -> Option<i64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use quickcheck::{QuickCheck, Testable};
+    use super::Coordinate;
+
+    fn quickcheck<A: Testable>(a: A) {
+        QuickCheck::new().tests(1000).quickcheck(a)
+    }
+
+    fn bits<C: Coordinate>(a: C, b: C) -> bool {
+        let (x, y) = (a.to_bits(), b.to_bits());
+        C::from_bits(x) == a && C::from_bits(y) == b && (a < b) == (x < y)
+    }
+
+    #[test]
+    fn bits_i16() {
+        quickcheck(bits::<i16> as fn(i16, i16) -> bool);
+        assert!(bits(i16::min_value(), i16::max_value()));
+        assert!(bits(-1i16, 0));
+    }
+
+    #[test]
+    fn bits_i32() {
+        quickcheck(bits::<i32> as fn(i32, i32) -> bool);
+        assert!(bits(i32::min_value(), i32::max_value()));
+        assert!(bits(-1i32, 0));
+    }
+
+    #[test]
+    fn bits_f32() {
+        fn bits_f32(a: f32, b: f32) -> bool {
+            bits(a, b) && bits(a * 1e30, b * 1e-30)
+        }
+        quickcheck(bits_f32 as fn(f32, f32) -> bool);
+        assert!(bits(-0.5f32, 0.5));
+        assert!(bits(-1e-40f32, 1e-40));
+        assert!(bits(::std::f32::NEG_INFINITY, ::std::f32::INFINITY));
+    }
+
+    #[test]
+    fn from_f64() {
+        assert_eq!(i16::max_value(), i16::from_f64(1e10));
+        assert_eq!(i16::min_value(), i16::from_f64(-1e10));
+        assert_eq!(-2, i16::from_f64(-1.5));
+        assert_eq!(i32::max_value(), i32::from_f64(1e10));
+        assert_eq!(70_000, i32::from_f64(69_999.6));
+        assert_eq!(0.25, f32::from_f64(0.25));
+    }
+}
@@ -0,0 +1,619 @@
+use std;
+use std::collections::{HashMap, HashSet};
+
+use super::{Rectangle, Point, Coordinate, SpatialIndex, ControlFlow, Touch};
+use super::{clip, distance2, larger, Ray};
+
+/// the furthest cell from the origin, keeps cell maths from
+/// overflowing for huge or infinite coordinates
+const CELL_LIMIT: f64 = 1e15;
+
+/// values that would be stored in more cells than this are kept in a
+/// list that every query checks instead
+const OVERSIZED: f64 = 1024.;
+
+type Cell = (i64, i64);
+
+/// A uniform grid, each value is stored in every cell its rectangle
+/// touches. This is quicker than an `RTree` for lots of values that
+/// are about the size of a cell, like bullets, and much slower for
+/// values that are far larger than a cell
+pub struct Grid<T, C = i16> {
+    cell_size: f64,
+    cells: HashMap<Cell, Vec<usize>>,
+    oversized: Vec<usize>,
+    values: Vec<Option<(Rectangle<C>, T)>>,
+    free: Vec<usize>,
+    len: usize,
+}
+
+impl<T, C: Coordinate> Grid<T, C> {
+    /// create an empty grid with square cells `cell_size` wide
+    pub fn new(cell_size: C) -> Grid<T, C> {
+        let cell_size = cell_size.to_f64();
+        assert!(cell_size > 0., "cell size must be positive");
+        Grid {
+            cell_size: cell_size,
+            cells: HashMap::new(),
+            oversized: vec![],
+            values: vec![],
+            free: vec![],
+            len: 0,
+        }
+    }
+
+    fn cell(&self, x: C) -> i64 {
+        self.cell_at(x.to_f64())
+    }
+
+    fn cell_at(&self, x: f64) -> i64 {
+        let c = (x / self.cell_size).floor();
+        c.max(-CELL_LIMIT).min(CELL_LIMIT) as i64
+    }
+
+    /// the first and last cell the rectangle touches
+    fn cells_of(&self, r: Rectangle<C>) -> (Cell, Cell) {
+        ((self.cell(r.min.x), self.cell(r.min.y)), (self.cell(r.max.x), self.cell(r.max.y)))
+    }
+
+    /// does the rectangle cover too many cells to be stored in them
+    fn is_oversized(&self, r: Rectangle<C>) -> bool {
+        let (min, max) = self.cells_of(r);
+        (max.0 - min.0 + 1) as f64 * (max.1 - min.1 + 1) as f64 > OVERSIZED
+    }
+
+    /// call `f` with each cell from `min` to `max` that holds values, in
+    /// the same order every time. If the range has more cells than are
+    /// in use only those are checked
+    fn visit_cells<F>(&self, min: Cell, max: Cell, mut f: F) -> ControlFlow
+        where F: FnMut(Cell, &[usize]) -> ControlFlow
+    {
+        if min.0 > max.0 || min.1 > max.1 {
+            return ControlFlow::Continue;
+        }
+
+        let span = (max.0 - min.0 + 1) as f64 * (max.1 - min.1 + 1) as f64;
+        if span > self.cells.len() as f64 {
+            // the map's order changes from run to run, so sort the cells
+            let mut inside: Vec<Cell> = self.cells
+                .keys()
+                .filter(|&&(x, y)| min.0 <= x && x <= max.0 && min.1 <= y && y <= max.1)
+                .cloned()
+                .collect();
+            inside.sort();
+            for cell in inside {
+                if f(cell, &self.cells[&cell]) == ControlFlow::Break {
+                    return ControlFlow::Break;
+                }
+            }
+        } else {
+            for x in min.0..max.0 + 1 {
+                for y in min.1..max.1 + 1 {
+                    if let Some(slots) = self.cells.get(&(x, y)) {
+                        if f((x, y), slots) == ControlFlow::Break {
+                            return ControlFlow::Break;
+                        }
+                    }
+                }
+            }
+        }
+        ControlFlow::Continue
+    }
+
+    fn entry(&self, slot: usize) -> (Rectangle<C>, &T) {
+        let &(rect, ref value) = self.values[slot].as_ref().unwrap();
+        (rect, value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.oversized.clear();
+        self.values.clear();
+        self.free.clear();
+        self.len = 0;
+    }
+
+    pub fn insert(&mut self, rect: Rectangle<C>, value: T) {
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.values[slot] = Some((rect, value));
+                slot
+            }
+            None => {
+                self.values.push(Some((rect, value)));
+                self.values.len() - 1
+            }
+        };
+        self.len += 1;
+
+        if self.is_oversized(rect) {
+            self.oversized.push(slot);
+            return;
+        }
+
+        let (min, max) = self.cells_of(rect);
+        for x in min.0..max.0 + 1 {
+            for y in min.1..max.1 + 1 {
+                self.cells.entry((x, y)).or_insert_with(Vec::new).push(slot);
+            }
+        }
+    }
+
+    /// remove a value from the grid, `rect` must match the rectangle it
+    /// was inserted with
+    pub fn remove(&mut self, rect: Rectangle<C>, value: &T) -> Option<T>
+        where T: PartialEq
+    {
+        let (min, max) = self.cells_of(rect);
+        let oversized = self.is_oversized(rect);
+        let slot = {
+            let slots = if oversized {
+                &self.oversized[..]
+            } else {
+                self.cells.get(&min).map_or(&[][..], |s| &s[..])
+            };
+            let found = slots.iter().position(|&slot| {
+                let (r, v) = self.entry(slot);
+                r == rect && v == value
+            });
+            match found {
+                Some(i) => slots[i],
+                None => return None,
+            }
+        };
+
+        if oversized {
+            let i = self.oversized.iter().position(|&s| s == slot).unwrap();
+            self.oversized.swap_remove(i);
+            self.free.push(slot);
+            self.len -= 1;
+            return self.values[slot].take().map(|(_, value)| value);
+        }
+
+        for x in min.0..max.0 + 1 {
+            for y in min.1..max.1 + 1 {
+                let empty = {
+                    let slots = self.cells.get_mut(&(x, y)).unwrap();
+                    let i = slots.iter().position(|&s| s == slot).unwrap();
+                    slots.swap_remove(i);
+                    slots.is_empty()
+                };
+                if empty {
+                    self.cells.remove(&(x, y));
+                }
+            }
+        }
+
+        self.free.push(slot);
+        self.len -= 1;
+        self.values[slot].take().map(|(_, value)| value)
+    }
+
+    /// call `visit` with the slot of every value that intersects `rect`
+    fn query_slots<F>(&self, rect: Rectangle<C>, mut visit: F) -> ControlFlow
+        where F: FnMut(usize) -> ControlFlow
+    {
+        for &slot in &self.oversized {
+            if self.entry(slot).0.intersects(rect) && visit(slot) == ControlFlow::Break {
+                return ControlFlow::Break;
+            }
+        }
+
+        let (min, max) = self.cells_of(rect);
+        self.visit_cells(min, max, |cell, slots| {
+            for &slot in slots {
+                let r = self.entry(slot).0;
+                if !r.intersects(rect) {
+                    continue;
+                }
+                // a value in many cells is only reported from the cell
+                // holding the corner of where it meets the query
+                let corner = (self.cell(larger(r.min.x, rect.min.x)), self.cell(larger(r.min.y, rect.min.y)));
+                if corner == cell && visit(slot) == ControlFlow::Break {
+                    return ControlFlow::Break;
+                }
+            }
+            ControlFlow::Continue
+        })
+    }
+
+    /// call `visit` with every value that intersects `rect` until it
+    /// returns `ControlFlow::Break`
+    pub fn query_with<F>(&self, rect: Rectangle<C>, mut visit: F) -> ControlFlow
+        where F: FnMut(&Rectangle<C>, &T) -> ControlFlow
+    {
+        self.query_slots(rect, |slot| {
+            let (r, value) = self.entry(slot);
+            visit(&r, value)
+        })
+    }
+
+    pub fn query(&self, rect: Rectangle<C>) -> Vec<(Rectangle<C>, &T)> {
+        let mut found = vec![];
+        self.query_slots(rect, |slot| {
+            found.push(self.entry(slot));
+            ControlFlow::Continue
+        });
+        found
+    }
+
+    /// find the `k` values closest to `point`, closest first. The cells
+    /// are searched in rings around the point until nothing further out
+    /// could be closer
+    pub fn nearest(&self, point: Point<C>, k: usize) -> Vec<(Rectangle<C>, &T)> {
+        if k == 0 || self.len == 0 {
+            return vec![];
+        }
+
+        let center = (self.cell(point.x), self.cell(point.y));
+        let last = self.cells
+            .keys()
+            .map(|&(x, y)| std::cmp::max((x - center.0).abs(), (y - center.1).abs()))
+            .max()
+            .unwrap_or(0);
+
+        let mut found: Vec<(f64, usize)> = vec![];
+        let by_distance = |a: &(f64, usize), b: &(f64, usize)| {
+            a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal)
+        };
+
+        // the rings cover more cells than are in use, check everything
+        if (2 * last + 1) as f64 * (2 * last + 1) as f64 > 4. * self.cells.len() as f64 {
+            for (slot, v) in self.values.iter().enumerate() {
+                if let Some((rect, _)) = *v {
+                    found.push((distance2(rect, point), slot));
+                }
+            }
+            found.sort_by(&by_distance);
+            found.truncate(k);
+            return found.into_iter().map(|(_, slot)| self.entry(slot)).collect();
+        }
+
+        let mut seen = HashSet::new();
+        for &slot in &self.oversized {
+            found.push((distance2(self.entry(slot).0, point), slot));
+        }
+        for ring in 0..last + 1 {
+            let (min, max) = ((center.0 - ring, center.1 - ring), (center.0 + ring, center.1 + ring));
+            for x in min.0..max.0 + 1 {
+                for y in min.1..max.1 + 1 {
+                    if x != min.0 && x != max.0 && y != min.1 && y != max.1 {
+                        continue;
+                    }
+                    for &slot in self.cells.get(&(x, y)).map_or(&[][..], |s| &s[..]) {
+                        if seen.insert(slot) {
+                            found.push((distance2(self.entry(slot).0, point), slot));
+                        }
+                    }
+                }
+            }
+
+            found.sort_by(&by_distance);
+            found.truncate(k);
+            // anything not seen yet is at least `ring` cells away
+            let reach = ring as f64 * self.cell_size;
+            if found.len() == k && found[k - 1].0 <= reach * reach {
+                break;
+            }
+        }
+        found.into_iter().map(|(_, slot)| self.entry(slot)).collect()
+    }
+
+    /// every pair of values that touch, each pair is only listed once.
+    /// The pairs are ordered by the slots of their values
+    pub fn intersecting_pairs(&self, touch: Touch) -> Vec<((Rectangle<C>, &T), (Rectangle<C>, &T))> {
+        let mut pairs = vec![];
+        for (slot, v) in self.values.iter().enumerate() {
+            let rect = match *v {
+                Some((rect, _)) => rect,
+                None => continue,
+            };
+            let mut found = vec![];
+            self.query_slots(rect, |other| {
+                if other > slot && touch.test(rect, self.entry(other).0) {
+                    found.push(other);
+                }
+                ControlFlow::Continue
+            });
+            found.sort();
+            for other in found {
+                pairs.push((self.entry(slot), self.entry(other)));
+            }
+        }
+        pairs
+    }
+
+    /// find every value that the segment from `from` to `to` touches,
+    /// ordered by where the segment enters them
+    pub fn segment(&self, from: Point<C>, to: Point<C>) -> Vec<(Rectangle<C>, &T)> {
+        let bounds = Rectangle { min: from, max: from }.extend(Rectangle { min: to, max: to });
+        let (min, max) = self.cells_of(bounds);
+
+        let mut seen = HashSet::new();
+        let mut hits = vec![];
+        for &slot in &self.oversized {
+            let (rect, value) = self.entry(slot);
+            if let Some((enter, _)) = clip(rect, from, to) {
+                hits.push((enter, rect, value));
+            }
+        }
+        self.visit_cells(min, max, |_, slots| {
+            for &slot in slots {
+                if !seen.insert(slot) {
+                    continue;
+                }
+                let (rect, value) = self.entry(slot);
+                if let Some((enter, _)) = clip(rect, from, to) {
+                    hits.push((enter, rect, value));
+                }
+            }
+            ControlFlow::Continue
+        });
+
+        hits.sort_by(|a, b| a.0.cmp(&b.0));
+        hits.into_iter().map(|(_, rect, value)| (rect, value)).collect()
+    }
+
+    /// cast a ray from `origin` along `direction` for `max_len` units,
+    /// returns the values it touches ordered by where the ray enters them
+    pub fn raycast(&self, origin: Point<C>, direction: Point<C>, max_len: f64) -> Vec<(Rectangle<C>, &T)> {
+        let ray = Ray::new(origin, direction, max_len);
+        let (from, to) = (ray.origin, ray.end());
+        let min = (self.cell_at(from.0.min(to.0)), self.cell_at(from.1.min(to.1)));
+        let max = (self.cell_at(from.0.max(to.0)), self.cell_at(from.1.max(to.1)));
+
+        let mut hits = vec![];
+        for &slot in &self.oversized {
+            let (rect, value) = self.entry(slot);
+            if let Some(enter) = ray.enter(rect) {
+                hits.push((enter, rect, value));
+            }
+        }
+
+        let mut seen = HashSet::new();
+        self.visit_cells(min, max, |_, slots| {
+            for &slot in slots {
+                if !seen.insert(slot) {
+                    continue;
+                }
+                let (rect, value) = self.entry(slot);
+                if let Some(enter) = ray.enter(rect) {
+                    hits.push((enter, rect, value));
+                }
+            }
+            ControlFlow::Continue
+        });
+
+        hits.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        hits.into_iter().map(|(_, rect, value)| (rect, value)).collect()
+    }
+}
+
+impl<T: PartialEq, C: Coordinate> SpatialIndex<T, C> for Grid<T, C> {
+    fn insert(&mut self, rect: Rectangle<C>, value: T) {
+        Grid::insert(self, rect, value)
+    }
+
+    fn remove(&mut self, rect: Rectangle<C>, value: &T) -> Option<T> {
+        Grid::remove(self, rect, value)
+    }
+
+    fn query(&self, rect: Rectangle<C>) -> Vec<(Rectangle<C>, &T)> {
+        Grid::query(self, rect)
+    }
+
+    fn intersecting_pairs(&self, touch: Touch) -> Vec<((Rectangle<C>, &T), (Rectangle<C>, &T))> {
+        Grid::intersecting_pairs(self, touch)
+    }
+
+    fn nearest(&self, point: Point<C>, k: usize) -> Vec<(Rectangle<C>, &T)> {
+        Grid::nearest(self, point, k)
+    }
+
+    fn raycast(&self, origin: Point<C>, direction: Point<C>, max_len: f64) -> Vec<(Rectangle<C>, &T)> {
+        Grid::raycast(self, origin, direction, max_len)
+    }
+
+    fn len(&self) -> usize {
+        Grid::len(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Rectangle, Point, RTree, Touch};
+    use super::super::distance2;
+    use super::Grid;
+    use super::super::SpatialIndex;
+
+    fn rect(ax: i16, ay: i16, bx: i16, by: i16) -> Rectangle {
+        Rectangle {
+            min: Point::new(ax, ay),
+            max: Point::new(bx, by),
+        }
+    }
+
+    /// a mix of small values and a few that span many cells
+    fn scene<S: SpatialIndex<u32>>(index: &mut S) {
+        for i in 0..400u32 {
+            let (x, y) = ((i % 20) as i16 * 7 - 70, (i / 20) as i16 * 7 - 70);
+            index.insert(rect(x, y, x + 3, y + 3), i);
+        }
+        index.insert(rect(-100, -2, 100, 2), 1000);
+        index.insert(rect(-2, -100, 2, 100), 1001);
+        index.insert(rect(500, 500, 501, 501), 1002);
+    }
+
+    fn sorted<C>(found: Vec<(Rectangle<C>, &u32)>) -> Vec<u32> {
+        let mut found: Vec<u32> = found.into_iter().map(|(_, &v)| v).collect();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn matches_rtree() {
+        let mut grid = Grid::new(16);
+        let mut tree = RTree::new();
+        scene(&mut grid);
+        scene(&mut tree);
+        assert_eq!(tree.len(), grid.len());
+
+        for &q in &[rect(-10, -10, 10, 10), rect(-200, -200, 200, 200), rect(30, -80, 31, 80), rect(200, 200, 300, 300)] {
+            assert_eq!(sorted(SpatialIndex::query(&tree, q)), sorted(grid.query(q)));
+        }
+
+        for &p in &[Point::new(0, 0), Point::new(33, -41), Point::new(400, 400), Point::new(-300, 0)] {
+            let distances = |found: Vec<(Rectangle, &u32)>| -> Vec<f64> {
+                found.into_iter().map(|(r, _)| distance2(r, p)).collect()
+            };
+            assert_eq!(distances(SpatialIndex::nearest(&tree, p, 5)), distances(grid.nearest(p, 5)));
+        }
+
+        fn ray<S: SpatialIndex<u32>>(index: &S) -> Vec<u32> {
+            sorted(index.raycast(Point::new(-90, -90), Point::new(1, 1), 200.))
+        }
+        assert_eq!(ray(&tree), ray(&grid));
+        assert!(ray(&grid).contains(&1000));
+
+        for i in 0..400u32 {
+            if i % 3 == 0 {
+                let (x, y) = ((i % 20) as i16 * 7 - 70, (i / 20) as i16 * 7 - 70);
+                assert_eq!(Some(i), grid.remove(rect(x, y, x + 3, y + 3), &i));
+                tree.remove(rect(x, y, x + 3, y + 3), &i);
+            }
+        }
+        assert_eq!(None, grid.remove(rect(0, 0, 3, 3), &5000));
+        assert_eq!(tree.len(), grid.len());
+        let all = rect(-1000, -1000, 1000, 1000);
+        assert_eq!(sorted(SpatialIndex::query(&tree, all)), sorted(grid.query(all)));
+    }
+
+    #[test]
+    fn trait_defaults() {
+        let mut grid = Grid::new(16);
+        let mut tree = RTree::new();
+        scene(&mut grid);
+        scene(&mut tree);
+        let pairs = |found: Vec<((Rectangle, &u32), (Rectangle, &u32))>| -> Vec<(u32, u32)> {
+            let mut found: Vec<_> = found.into_iter()
+                .map(|((_, &a), (_, &b))| if a < b { (a, b) } else { (b, a) })
+                .collect();
+            found.sort();
+            found
+        };
+        assert_eq!(pairs(tree.intersecting_pairs(Touch::Intersects)),
+                   pairs(SpatialIndex::intersecting_pairs(&grid, Touch::Intersects)));
+
+        let shift = |r: Rectangle, &v: &u32| if v >= 1000 { r } else { r.translate(Point::new(200, 0)) };
+        assert!(!SpatialIndex::refit(&mut grid, shift));
+        tree.refit(shift);
+        let q = rect(100, -200, 300, 200);
+        assert_eq!(400, grid.query(q).into_iter().filter(|&(_, &v)| v < 1000).count());
+        assert_eq!(sorted(SpatialIndex::query(&tree, q)), sorted(grid.query(q)));
+    }
+
+    #[test]
+    fn deterministic() {
+        // each map is seeded differently, so two grids built the same
+        // way would disagree if the order came from the map
+        let (mut a, mut b) = (Grid::new(16), Grid::new(16));
+        scene(&mut a);
+        scene(&mut b);
+        let values = |found: Vec<(Rectangle, &u32)>| -> Vec<u32> {
+            found.into_iter().map(|(_, &v)| v).collect()
+        };
+        let all = rect(-1000, -1000, 1000, 1000);
+        assert_eq!(values(a.query(all)), values(b.query(all)));
+
+        let pairs = |found: Vec<((Rectangle, &u32), (Rectangle, &u32))>| -> Vec<(u32, u32)> {
+            found.into_iter().map(|((_, &a), (_, &b))| (a, b)).collect()
+        };
+        assert_eq!(pairs(a.intersecting_pairs(Touch::Intersects)), pairs(b.intersecting_pairs(Touch::Intersects)));
+
+        // only the required methods, so the trait's defaults are used
+        struct Plain(Grid<u32>);
+        impl SpatialIndex<u32> for Plain {
+            fn insert(&mut self, rect: Rectangle, value: u32) {
+                self.0.insert(rect, value)
+            }
+            fn remove(&mut self, rect: Rectangle, value: &u32) -> Option<u32> {
+                self.0.remove(rect, value)
+            }
+            fn query(&self, rect: Rectangle) -> Vec<(Rectangle, &u32)> {
+                self.0.query(rect)
+            }
+            fn nearest(&self, point: Point, k: usize) -> Vec<(Rectangle, &u32)> {
+                self.0.nearest(point, k)
+            }
+            fn raycast(&self, origin: Point, direction: Point, max_len: f64) -> Vec<(Rectangle, &u32)> {
+                self.0.raycast(origin, direction, max_len)
+            }
+            fn len(&self) -> usize {
+                self.0.len()
+            }
+        }
+        let (mut a, mut b) = (Plain(Grid::new(16)), Plain(Grid::new(16)));
+        scene(&mut a);
+        scene(&mut b);
+        let found = pairs(a.intersecting_pairs(Touch::Intersects));
+        assert_eq!(found, pairs(b.intersecting_pairs(Touch::Intersects)));
+        assert_eq!(pairs(a.0.intersecting_pairs(Touch::Intersects)).len(), found.len());
+    }
+
+    #[test]
+    fn reports_once() {
+        let mut grid = Grid::new(4);
+        grid.insert(rect(-50, -50, 50, 50), 1u32);
+        grid.insert(rect(10, 10, 10, 10), 2);
+        assert_eq!(vec![1, 2], sorted(grid.query(rect(0, 0, 20, 20))));
+        assert_eq!(vec![1], sorted(grid.query(rect(-40, 30, -30, 40))));
+        assert_eq!(2, grid.raycast(Point::new(-60, -60), Point::new(1, 1), 1000.).len());
+
+        assert_eq!(Some(1), grid.remove(rect(-50, -50, 50, 50), &1));
+        assert!(grid.cells.len() == 1);
+        grid.clear();
+        assert!(grid.is_empty());
+    }
+
+    #[test]
+    fn oversized() {
+        let mut grid: Grid<u32, i32> = Grid::new(1);
+        let irect = |ax, ay, bx, by| Rectangle { min: Point::new(ax, ay), max: Point::new(bx, by) };
+        let (min, max) = (i32::min_value(), i32::max_value());
+        let all = irect(min, min, max, max);
+        grid.insert(all, 1);
+        grid.insert(irect(-1000, 0, 1000, 0), 2);
+        grid.insert(irect(5, 5, 6, 6), 3);
+        assert!(grid.cells.len() <= 4);
+
+        assert_eq!(vec![1, 2, 3], sorted(grid.query(irect(0, 0, 10, 10))));
+        assert_eq!(vec![1], sorted(grid.query(irect(-10, -10, -5, -5))));
+        assert_eq!(3, grid.nearest(Point::new(7, 7), 3).len());
+        assert_eq!(2, grid.raycast(Point::new(0, -5), Point::new(0, 1), 10.).len());
+
+        assert_eq!(Some(1), grid.remove(all, &1));
+        assert_eq!(None, grid.remove(all, &1));
+        assert_eq!(Some(2), grid.remove(irect(-1000, 0, 1000, 0), &2));
+        assert_eq!(vec![3], sorted(grid.query(all)));
+    }
+
+    #[test]
+    fn float_coordinates() {
+        let mut grid: Grid<u32, f32> = Grid::new(0.5);
+        let frect = |ax, ay, bx, by| Rectangle { min: Point::new(ax, ay), max: Point::new(bx, by) };
+        let r = frect(-0.25, -0.25, 0.25, 0.25);
+        grid.insert(r, 1);
+        grid.insert(frect(3., 3., 3.5, 3.5), 2);
+        assert_eq!(1, grid.query(frect(0.1, 0.1, 0.2, 0.2)).len());
+        assert_eq!(Some((r, &1)), grid.nearest(Point::new(1., 0.), 1).pop());
+        assert_eq!(2, grid.nearest(Point::new(1., 0.), 10).len());
+    }
+}
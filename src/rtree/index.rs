@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use super::{RTree, Rectangle, Point, Coordinate, Touch};
+
+/// a rectangle covering every coordinate
+fn everything<C: Coordinate>() -> Rectangle<C> {
+    Rectangle {
+        min: Point::new(C::min_value(), C::min_value()),
+        max: Point::new(C::max_value(), C::max_value()),
+    }
+}
+
+/// The operations every spatial index supports, so code can be written
+/// against whichever one suits its data best
+pub trait SpatialIndex<T: PartialEq, C: Coordinate = i16> {
+    fn insert(&mut self, rect: Rectangle<C>, value: T);
+
+    /// remove a value, `rect` must match the rectangle it was
+    /// inserted with
+    fn remove(&mut self, rect: Rectangle<C>, value: &T) -> Option<T>;
+
+    /// move a value from `old` to `new`, returns false if the value
+    /// was not in the index
+    fn update(&mut self, old: Rectangle<C>, new: Rectangle<C>, value: &T) -> bool {
+        match self.remove(old, value) {
+            Some(value) => {
+                self.insert(new, value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// move every value to the rectangle `f` gives for it, returns true
+    /// if the index was rebuilt to do so. By default each value that
+    /// moved is updated on its own
+    fn refit<F>(&mut self, mut f: F) -> bool
+        where F: FnMut(Rectangle<C>, &T) -> Rectangle<C>,
+              T: Clone
+    {
+        let moved: Vec<_> = self.query(everything())
+            .into_iter()
+            .filter_map(|(old, value)| {
+                let new = f(old, value);
+                if new != old { Some((old, new, value.clone())) } else { None }
+            })
+            .collect();
+        for (old, new, value) in moved {
+            self.update(old, new, &value);
+        }
+        false
+    }
+
+    /// every value that intersects `rect`
+    fn query(&self, rect: Rectangle<C>) -> Vec<(Rectangle<C>, &T)>;
+
+    /// every pair of values that touch, each pair is only listed once.
+    /// By default the pairs are ordered by where their values are
+    /// found in a query of everything
+    fn intersecting_pairs(&self, touch: Touch) -> Vec<((Rectangle<C>, &T), (Rectangle<C>, &T))> {
+        let all = self.query(everything());
+        // every value is stored once, so its address says which one it is
+        let order: HashMap<*const T, usize> = all.iter()
+            .enumerate()
+            .map(|(i, &(_, v))| (v as *const T, i))
+            .collect();
+
+        let mut pairs = vec![];
+        for (i, &(a, va)) in all.iter().enumerate() {
+            let mut found: Vec<_> = self.query(a)
+                .into_iter()
+                .filter(|&(b, vb)| order[&(vb as *const T)] > i && touch.test(a, b))
+                .map(|(b, vb)| (order[&(vb as *const T)], b, vb))
+                .collect();
+            found.sort_by_key(|&(j, _, _)| j);
+            pairs.extend(found.into_iter().map(|(_, b, vb)| ((a, va), (b, vb))));
+        }
+        pairs
+    }
+
+    /// the `k` values closest to `point`, closest first
+    fn nearest(&self, point: Point<C>, k: usize) -> Vec<(Rectangle<C>, &T)>;
+
+    /// the values a ray touches ordered by where it enters them
+    fn raycast(&self, origin: Point<C>, direction: Point<C>, max_len: f64) -> Vec<(Rectangle<C>, &T)>;
+
+    /// the number of values in the index
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: PartialEq, C: Coordinate> SpatialIndex<T, C> for RTree<T, C> {
+    fn insert(&mut self, rect: Rectangle<C>, value: T) {
+        RTree::insert(self, rect, value)
+    }
+
+    fn remove(&mut self, rect: Rectangle<C>, value: &T) -> Option<T> {
+        RTree::remove(self, rect, value)
+    }
+
+    fn update(&mut self, old: Rectangle<C>, new: Rectangle<C>, value: &T) -> bool {
+        RTree::update(self, old, new, value)
+    }
+
+    fn refit<F>(&mut self, f: F) -> bool
+        where F: FnMut(Rectangle<C>, &T) -> Rectangle<C>,
+              T: Clone
+    {
+        RTree::refit(self, f)
+    }
+
+    fn query(&self, rect: Rectangle<C>) -> Vec<(Rectangle<C>, &T)> {
+        RTree::query(self, rect).map(|(&r, v)| (r, v)).collect()
+    }
+
+    fn intersecting_pairs(&self, touch: Touch) -> Vec<((Rectangle<C>, &T), (Rectangle<C>, &T))> {
+        RTree::intersecting_pairs(self, touch)
+    }
+
+    fn nearest(&self, point: Point<C>, k: usize) -> Vec<(Rectangle<C>, &T)> {
+        RTree::nearest(self, point, k)
+    }
+
+    fn raycast(&self, origin: Point<C>, direction: Point<C>, max_len: f64) -> Vec<(Rectangle<C>, &T)> {
+        RTree::raycast(self, origin, direction, max_len)
+    }
+
+    fn len(&self) -> usize {
+        RTree::len(self)
+    }
+}
@@ -1,108 +1,320 @@
-
 use std;
+use std::borrow::BorrowMut;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+mod coordinate;
+mod grid;
+mod index;
+mod snapshot;
+
+pub use self::coordinate::Coordinate;
+pub use self::grid::Grid;
+pub use self::index::SpatialIndex;
+pub use self::snapshot::{Codec, Snapshot};
+#[cfg(feature = "mmap")]
+pub use self::snapshot::MappedFile;
 
 const FANOUT_SIZE: usize = 8;
 
+/// lists shorter than this are not worth sorting across threads
+#[cfg(feature = "parallel")]
+const PARALLEL_SORT: usize = 4096;
+
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Point {
-    pub x: i16,
-    pub y: i16,
+pub struct Point<C = i16> {
+    pub x: C,
+    pub y: C,
 }
 
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
-pub struct MortonPoint(pub u32);
+pub struct MortonPoint<K = u32>(pub K);
+
+/// a point's distance along a hilbert curve, unlike a morton code
+/// neighbouring keys are always neighbouring points
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HilbertPoint<K = u32>(pub K);
+
+fn interleave(x: u64) -> u64 {
+    let x = (x | (x << 16)) & 0x0000_ffff_0000_ffff;
+    let x = (x | (x << 8)) & 0x00ff_00ff_00ff_00ff;
+    let x = (x | (x << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    let x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+
+fn deinterleave(x: u64) -> u64 {
+    let x = x & 0x5555_5555_5555_5555;
+    let x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    let x = (x | (x >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    let x = (x | (x >> 4)) & 0x00ff_00ff_00ff_00ff;
+    let x = (x | (x >> 8)) & 0x0000_ffff_0000_ffff;
+    (x | (x >> 16)) & 0x0000_0000_ffff_ffff
+}
 
-fn interleave(x: u32) -> u32 {
-    let x = (x | (x << 8)) & 0x00ff_00ff;
-    let x = (x | (x << 4)) & 0x0f0f_0f0f;
-    let x = (x | (x << 2)) & 0x3333_3333;
-    (x | (x << 1)) & 0x5555_5555
+/// distance along a hilbert curve covering a grid `bits` wide
+fn hilbert_encode(x: u64, y: u64, bits: u32) -> u64 {
+    let mask = (1 << bits) - 1;
+    let (mut x, mut y, mut d) = (x, y, 0);
+    let mut s = 1 << (bits - 1);
+    while s > 0 {
+        let rx = (x & s != 0) as u64;
+        let ry = (y & s != 0) as u64;
+        d += s * s * ((3 * rx) ^ ry);
+        if ry == 0 {
+            if rx == 1 {
+                x = mask - x;
+                y = mask - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s >>= 1;
+    }
+    d
 }
 
-fn deinterleave(x: u32) -> u32 {
-    let x = x & 0x5555_5555;
-    let x = (x | (x >> 1)) & 0x3333_3333;
-    let x = (x | (x >> 2)) & 0x0f0f_0f0f;
-    let x = (x | (x >> 4)) & 0x00ff_00ff;
-    (x | (x >> 8)) & 0x0000_ffff
+fn hilbert_decode(d: u64, bits: u32) -> (u64, u64) {
+    let (mut x, mut y, mut t) = (0, 0, d);
+    let mut s = 1;
+    while s < (1 << bits) {
+        let rx = 1 & (t >> 1);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t >>= 2;
+        s <<= 1;
+    }
+    (x, y)
 }
 
-impl Point {
+impl<C> Point<C> {
     /// Create a new point
-    pub fn new(x: i16, y: i16) -> Point {
+    pub fn new(x: C, y: C) -> Point<C> {
         Point { x: x, y: y }
     }
 }
 
-impl From<Point> for MortonPoint {
-    fn from(p: Point) -> MortonPoint {
-        let x = (p.x as u32).wrapping_sub(i16::min_value() as u32);
-        let y = (p.y as u32).wrapping_sub(i16::min_value() as u32);
-        MortonPoint(interleave(x) | interleave(y) << 1)
+impl<C: Coordinate> From<Point<C>> for MortonPoint<C::Key> {
+    fn from(p: Point<C>) -> MortonPoint<C::Key> {
+        let x = interleave(p.x.to_bits() as u64);
+        let y = interleave(p.y.to_bits() as u64);
+        MortonPoint(C::key(x | y << 1))
+    }
+}
+
+impl<C: Coordinate> From<MortonPoint<C::Key>> for Point<C> {
+    fn from(p: MortonPoint<C::Key>) -> Point<C> {
+        let key: u64 = p.0.into();
+        Point {
+            x: C::from_bits(deinterleave(key) as u32),
+            y: C::from_bits(deinterleave(key >> 1) as u32),
+        }
+    }
+}
+
+impl<C: Coordinate> From<Point<C>> for HilbertPoint<C::Key> {
+    fn from(p: Point<C>) -> HilbertPoint<C::Key> {
+        let d = hilbert_encode(p.x.to_bits() as u64, p.y.to_bits() as u64, C::bits());
+        HilbertPoint(C::key(d))
     }
 }
 
-impl From<MortonPoint> for Point {
-    fn from(p: MortonPoint) -> Point {
-        let x = deinterleave(p.0).wrapping_add(i16::min_value() as u32);
-        let y = deinterleave(p.0 >> 1).wrapping_add(i16::min_value() as u32);
+impl<C: Coordinate> From<HilbertPoint<C::Key>> for Point<C> {
+    fn from(p: HilbertPoint<C::Key>) -> Point<C> {
+        let (x, y) = hilbert_decode(p.0.into(), C::bits());
         Point {
-            x: x as i16,
-            y: y as i16,
+            x: C::from_bits(x as u32),
+            y: C::from_bits(y as u32),
         }
     }
 }
 
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Rectangle {
-    pub min: Point,
-    pub max: Point,
+pub struct Rectangle<C = i16> {
+    pub min: Point<C>,
+    pub max: Point<C>,
+}
+
+fn smaller<C: PartialOrd>(a: C, b: C) -> C {
+    if b < a { b } else { a }
+}
+
+fn larger<C: PartialOrd>(a: C, b: C) -> C {
+    if b > a { b } else { a }
 }
 
-impl Rectangle {
+impl<C: Coordinate> Rectangle<C> {
+    /// a rectangle that covers nothing, extending it by another rectangle
+    /// gives back the other rectangle
+    fn empty() -> Rectangle<C> {
+        Rectangle {
+            min: Point::new(C::max_value(), C::max_value()),
+            max: Point::new(C::min_value(), C::min_value()),
+        }
+    }
+
     /// does anypoint in the rectangle touch the lhs.
     /// this includes the case where two rectangles share an edge
-    pub fn intersects(&self, b: Rectangle) -> bool {
+    pub fn intersects(&self, b: Rectangle<C>) -> bool {
         let a = self;
         !(a.min.x > b.max.x || a.min.y > b.max.y || b.min.x > a.max.x || b.min.y > a.max.y)
     }
 
     /// check to see if lhs overlaops with b. This does not include
     /// the case where they share an edge
-    pub fn overlaps(&self, b: Rectangle) -> bool {
+    pub fn overlaps(&self, b: Rectangle<C>) -> bool {
         let a = self;
         !(a.min.x >= b.max.x || a.min.y >= b.max.y || b.min.x >= a.max.x || b.min.y >= a.max.y)
     }
 
-    pub fn extend(self, b: Rectangle) -> Rectangle {
+    /// a rectangle `width` by `height` around `center`, odd sizes put
+    /// the extra unit on the max side
+    pub fn from_center_size(center: Point<C>, width: C, height: C) -> Rectangle<C> {
+        let min = Point::new(center.x.saturating_sub(width.half()),
+                             center.y.saturating_sub(height.half()));
+        Rectangle {
+            min: min,
+            max: Point::new(min.x.saturating_add(width), min.y.saturating_add(height)),
+        }
+    }
+
+    /// the rectangle moved by `by`, coordinates stop at the
+    /// edge of what `C` can hold instead of wrapping around
+    pub fn translate(self, by: Point<C>) -> Rectangle<C> {
+        Rectangle {
+            min: Point::new(self.min.x.saturating_add(by.x), self.min.y.saturating_add(by.y)),
+            max: Point::new(self.max.x.saturating_add(by.x), self.max.y.saturating_add(by.y)),
+        }
+    }
+
+    /// the rectangle grown by `amount` on every side, a negative
+    /// amount shrinks it
+    pub fn expand_by(self, amount: C) -> Rectangle<C> {
+        Rectangle {
+            min: Point::new(self.min.x.saturating_sub(amount), self.min.y.saturating_sub(amount)),
+            max: Point::new(self.max.x.saturating_add(amount), self.max.y.saturating_add(amount)),
+        }
+    }
+
+    /// the width of the rectangle, saturates if it is wider than
+    /// `C` can hold
+    pub fn width(&self) -> C {
+        if self.max.x < self.min.x { C::zero() } else { self.max.x.saturating_sub(self.min.x) }
+    }
+
+    /// the height of the rectangle, saturates if it is taller than
+    /// `C` can hold
+    pub fn height(&self) -> C {
+        if self.max.y < self.min.y { C::zero() } else { self.max.y.saturating_sub(self.min.y) }
+    }
+
+    /// the area of the rectangle, this is calculated as a f64 so it
+    /// cannot overflow
+    pub fn area(&self) -> f64 {
+        let (w, h) = size(*self);
+        w * h
+    }
+
+    /// the center of the rectangle, rounded to the nearest coordinate
+    pub fn center(&self) -> Point<C> {
+        Point::new(C::from_f64((self.min.x.to_f64() + self.max.x.to_f64()) / 2.),
+                   C::from_f64((self.min.y.to_f64() + self.max.y.to_f64()) / 2.))
+    }
+
+    /// is the point inside of the rectangle or on its edge
+    pub fn contains_point(&self, p: Point<C>) -> bool {
+        self.min.x <= p.x && p.x <= self.max.x && self.min.y <= p.y && p.y <= self.max.y
+    }
+
+    /// is all of b inside of the rectangle, b may share an edge with it
+    pub fn contains_rect(&self, b: Rectangle<C>) -> bool {
+        self.contains_point(b.min) && self.contains_point(b.max)
+    }
+
+    /// the rectangle covered by both self and b. Rectangles that only
+    /// share an edge give a rectangle with no width or height
+    pub fn intersection(&self, b: Rectangle<C>) -> Option<Rectangle<C>> {
+        if !self.intersects(b) {
+            return None;
+        }
+        let a = self;
+        Some(Rectangle {
+            min: Point::new(larger(a.min.x, b.min.x), larger(a.min.y, b.min.y)),
+            max: Point::new(smaller(a.max.x, b.max.x), smaller(a.max.y, b.max.y)),
+        })
+    }
+
+    /// the distance from the point to the closest point in the rectangle,
+    /// points inside of the rectangle are 0 away
+    pub fn distance_to_point(&self, p: Point<C>) -> f64 {
+        distance2(*self, p).sqrt()
+    }
+
+    pub fn extend(self, b: Rectangle<C>) -> Rectangle<C> {
         let a = self;
         Rectangle {
             min: Point {
-                x: std::cmp::min(a.min.x, b.min.x),
-                y: std::cmp::min(a.min.y, b.min.y),
+                x: smaller(a.min.x, b.min.x),
+                y: smaller(a.min.y, b.min.y),
             },
             max: Point {
-                x: std::cmp::max(a.max.x, b.max.x),
-                y: std::cmp::max(a.max.y, b.max.y),
+                x: larger(a.max.x, b.max.x),
+                y: larger(a.max.y, b.max.y),
             },
         }
     }
 }
 
+/// how two rectangles have to touch to count as a pair
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Touch {
+    /// the rectangles share some area, see `Rectangle::overlaps`
+    Overlaps,
+    /// the rectangles share some area or an edge, see `Rectangle::intersects`
+    Intersects,
+}
+
+impl Touch {
+    fn test<C: Coordinate>(self, a: Rectangle<C>, b: Rectangle<C>) -> bool {
+        match self {
+            Touch::Overlaps => a.overlaps(b),
+            Touch::Intersects => a.intersects(b),
+        }
+    }
+}
+
+/// returned by visitors to say if a search should keep going
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ControlFlow {
+    Continue,
+    Break,
+}
+
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
-pub struct MortonRectangle {
-    pub min: MortonPoint,
-    pub max: MortonPoint,
+pub struct MortonRectangle<K = u32> {
+    pub min: MortonPoint<K>,
+    pub max: MortonPoint<K>,
 }
 
-impl MortonRectangle {
-    fn center(&self) -> u32 {
-        (self.min.0 >> 1) + (self.max.0 >> 1)
+impl<K: Copy + Into<u64>> MortonRectangle<K> {
+    fn center(&self) -> u64 {
+        let (min, max): (u64, u64) = (self.min.0.into(), self.max.0.into());
+        (min >> 1) + (max >> 1)
     }
 }
 
-impl From<Rectangle> for MortonRectangle {
-    fn from(p: Rectangle) -> MortonRectangle {
+impl<C: Coordinate> From<Rectangle<C>> for MortonRectangle<C::Key> {
+    fn from(p: Rectangle<C>) -> MortonRectangle<C::Key> {
         MortonRectangle {
             min: p.min.into(),
             max: p.max.into(),
@@ -110,8 +322,8 @@ impl From<Rectangle> for MortonRectangle {
     }
 }
 
-impl From<MortonRectangle> for Rectangle {
-    fn from(p: MortonRectangle) -> Rectangle {
+impl<C: Coordinate> From<MortonRectangle<C::Key>> for Rectangle<C> {
+    fn from(p: MortonRectangle<C::Key>) -> Rectangle<C> {
         Rectangle {
             min: p.min.into(),
             max: p.max.into(),
@@ -119,6 +331,54 @@ impl From<MortonRectangle> for Rectangle {
     }
 }
 
+/// how values are ordered into leaves when the tree is built in bulk
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Strategy {
+    /// sort by the morton code of the center of each rectangle
+    Morton,
+    /// sort by the distance along a hilbert curve of the center of
+    /// each rectangle
+    Hilbert,
+    /// sort tile recursive, slice by x then pack each slice by y. This
+    /// copes better with large or elongated rectangles
+    SortTileRecursive,
+}
+
+impl Default for Strategy {
+    fn default() -> Strategy {
+        Strategy::Morton
+    }
+}
+
+/// measurements of how tightly the nodes fit what they contain,
+/// smaller is better for all of them
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Quality {
+    /// the area of every node added together
+    pub area: f64,
+    /// the area shared between sibling nodes
+    pub overlap: f64,
+    /// the area inside of nodes not covered by any of their children
+    pub dead_space: f64,
+}
+
+/// the shape of a tree, see `RTree::stats`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// the number of levels, a tree that is only a root leaf has a depth of 1
+    pub depth: usize,
+    /// the number of nodes on each level, starting from the root
+    pub nodes_per_level: Vec<usize>,
+    /// the children in use over the room nodes have for children
+    pub fill_factor: f64,
+    /// the area shared between sibling nodes
+    pub overlap: f64,
+    /// the area inside of nodes not covered by any of their children
+    pub dead_space: f64,
+    /// value slots that have been allocated but are not holding a value
+    pub dead_slots: usize,
+}
+
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub enum PointsAt {
     Leaf,
@@ -126,298 +386,2633 @@ pub enum PointsAt {
 }
 
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Node {
-    rect: Rectangle,
+pub struct Node<C> {
+    rect: Rectangle<C>,
     leaf: PointsAt,
     start: usize,
     stop: usize,
+    /// the number of values below the node
+    count: usize,
+}
+
+impl<C: Coordinate> Node<C> {
+    /// a node with no children, every node owns a block of FANOUT_SIZE
+    /// slots starting at `start` that it can grow into
+    fn empty(leaf: PointsAt, start: usize) -> Node<C> {
+        Node {
+            rect: Rectangle::empty(),
+            leaf: leaf,
+            start: start,
+            stop: start,
+            count: 0,
+        }
+    }
+}
+
+/// the width and height of the rectangle, rectangles with no size
+/// have no width or height
+fn size<C: Coordinate>(r: Rectangle<C>) -> (f64, f64) {
+    if r.min.x > r.max.x || r.min.y > r.max.y {
+        return (0., 0.);
+    }
+    (r.max.x.to_f64() - r.min.x.to_f64(), r.max.y.to_f64() - r.min.y.to_f64())
+}
+
+/// half of the perimeter of the rectangle
+fn margin<C: Coordinate>(r: Rectangle<C>) -> f64 {
+    let (w, h) = size(r);
+    w + h
+}
+
+/// the area covered by at least one of the rectangles, the edges of
+/// the rectangles cut the space into cells which are checked one by one
+fn union_area<C: Coordinate>(rects: &[Rectangle<C>]) -> f64 {
+    let edges = |mut edges: Vec<f64>| {
+        edges.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        edges.dedup();
+        edges
+    };
+    let xs = edges(rects.iter().flat_map(|r| vec![r.min.x.to_f64(), r.max.x.to_f64()]).collect());
+    let ys = edges(rects.iter().flat_map(|r| vec![r.min.y.to_f64(), r.max.y.to_f64()]).collect());
+
+    let mut total = 0.;
+    for x in xs.windows(2) {
+        for y in ys.windows(2) {
+            let covered = rects.iter().any(|r| {
+                r.min.x.to_f64() <= x[0] && x[1] <= r.max.x.to_f64() &&
+                r.min.y.to_f64() <= y[0] && y[1] <= r.max.y.to_f64()
+            });
+            if covered {
+                total += (x[1] - x[0]) * (y[1] - y[0]);
+            }
+        }
+    }
+    total
+}
+
+/// an insertion sort that gives up and falls back to a full sort once
+/// it has moved more items than there are, input that is already nearly
+/// sorted is sorted in close to linear time
+fn sort_adaptive<A, F>(items: &mut [A], cmp: F)
+    where F: Fn(&A, &A) -> Ordering
+{
+    let mut budget = items.len();
+    for i in 1..items.len() {
+        let mut j = i;
+        while j > 0 && cmp(&items[j - 1], &items[j]) == Ordering::Greater {
+            if budget == 0 {
+                items.sort_by(|a, b| cmp(a, b));
+                return;
+            }
+            budget -= 1;
+            items.swap(j - 1, j);
+            j -= 1;
+        }
+    }
+}
+
+/// a f64 that can be used as a sort key, NaN is equal to everything
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+struct SortKey(f64);
+
+impl Eq for SortKey {}
+
+impl Ord for SortKey {
+    fn cmp(&self, other: &SortKey) -> Ordering {
+        self.partial_cmp(other).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// sort `items` by `key` using `sort_adaptive`
+#[cfg(not(feature = "parallel"))]
+fn sort_by_key<A, K, F>(items: &mut [A], key: F)
+    where K: Ord + Send,
+          F: Fn(&A) -> K
+{
+    sort_adaptive(items, |a, b| key(a).cmp(&key(b)));
+}
+
+/// sort `items` by `key`, long lists have their keys sorted across
+/// threads and the items are then moved into place
+#[cfg(feature = "parallel")]
+fn sort_by_key<A, K, F>(items: &mut [A], key: F)
+    where K: Ord + Send,
+          F: Fn(&A) -> K
+{
+    if items.len() < PARALLEL_SORT {
+        return sort_adaptive(items, |a, b| key(a).cmp(&key(b)));
+    }
+
+    let mut keys: Vec<(K, usize)> = items.iter().map(&key).zip(0..).collect();
+    keys.par_sort_unstable();
+
+    // slot i should hold the item that started at order[i], follow
+    // each cycle of the permutation swapping items into place
+    let mut order: Vec<usize> = keys.into_iter().map(|(_, i)| i).collect();
+    for i in 0..order.len() {
+        let mut j = i;
+        loop {
+            let k = order[j];
+            order[j] = j;
+            if k == i {
+                break;
+            }
+            items.swap(j, k);
+            j = k;
+        }
+    }
+}
+
+/// sort by the center of each rectangle along one axis, `axis` picks
+/// out the min and max of that axis
+fn sort_by_center<A, C, F, G>(items: &mut [A], rect: &F, axis: G)
+    where C: Coordinate,
+          F: Fn(&A) -> Rectangle<C>,
+          G: Fn(Rectangle<C>) -> (C, C)
+{
+    let key = |a: &A| {
+        let (min, max) = axis(rect(a));
+        min.to_f64() + max.to_f64()
+    };
+    sort_by_key(items, |a| SortKey(key(a)));
+}
+
+/// order items for sort tile recursive packing. The items are sorted by x
+/// and cut into slices, each slice is then sorted by y so every run of
+/// FANOUT_SIZE items forms a tile
+fn sort_tiles<A, C, F>(items: &mut [A], rect: F)
+    where C: Coordinate,
+          F: Fn(&A) -> Rectangle<C>
+{
+    let nodes = (items.len() + FANOUT_SIZE - 1) / FANOUT_SIZE;
+    let mut slices = 1;
+    while slices * slices < nodes {
+        slices += 1;
+    }
+    let per_slice = (nodes + slices - 1) / slices * FANOUT_SIZE;
+
+    sort_by_center(items, &rect, |r| (r.min.x, r.max.x));
+    for slice in items.chunks_mut(std::cmp::max(1, per_slice)) {
+        sort_by_center(slice, &rect, |r| (r.min.y, r.max.y));
+    }
+}
+
+/// sort the entries along the axis their centers are most spread out on
+/// so they can be cut in half for a split
+fn sort_for_split<A, C, F>(entries: &mut Vec<A>, rect: F)
+    where C: Coordinate,
+          F: Fn(&A) -> Rectangle<C>
+{
+    let (mut lo, mut hi) = ((std::f64::INFINITY, std::f64::INFINITY),
+                            (std::f64::NEG_INFINITY, std::f64::NEG_INFINITY));
+    for e in entries.iter() {
+        let r = rect(e);
+        let (x, y) = (r.min.x.to_f64() + r.max.x.to_f64(), r.min.y.to_f64() + r.max.y.to_f64());
+        lo = (lo.0.min(x), lo.1.min(y));
+        hi = (hi.0.max(x), hi.1.max(y));
+    }
+
+    if hi.0 - lo.0 >= hi.1 - lo.1 {
+        sort_by_center(entries, &rect, |r| (r.min.x, r.max.x));
+    } else {
+        sort_by_center(entries, &rect, |r| (r.min.y, r.max.y));
+    }
 }
 
-pub struct RTree<T> {
-    nodes: Vec<Node>,
-    values: Vec<(Rectangle, T)>,
+/// The tree is stored flat, the root is always the last node. Every
+/// node owns a block of FANOUT_SIZE slots in either `nodes` or `values`,
+/// only `start..stop` of the block is in use. This lets values be
+/// added and removed by only touching a leaf and its ancestors.
+pub struct RTree<T, C = i16> {
+    nodes: Vec<Node<C>>,
+    values: Vec<Option<(Rectangle<C>, T)>>,
+    free_nodes: Vec<usize>,
+    free_values: Vec<usize>,
+    strategy: Strategy,
+    /// the area of all of the nodes per value after the tree was last
+    /// sorted, or when it was first refit if it was built by inserting
+    sorted_area: Option<f64>,
+    resort_threshold: f64,
 }
 
-impl<T> RTree<T> {
-    pub fn new() -> RTree<T> {
+impl<T, C: Coordinate> RTree<T, C> {
+    pub fn new() -> RTree<T, C> {
+        RTree::with_strategy(Strategy::default())
+    }
+
+    /// create an empty tree that is built using `strategy`
+    pub fn with_strategy(strategy: Strategy) -> RTree<T, C> {
         RTree {
             nodes: vec![],
             values: vec![],
+            free_nodes: vec![],
+            free_values: vec![],
+            strategy: strategy,
+            sorted_area: None,
+            resort_threshold: 1.5,
         }
     }
 
+    /// change how the tree is built, the tree is rebuilt with the new strategy
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        self.strategy = strategy;
+        self.rebuild();
+    }
+
+    /// how much the nodes can grow during `refit` before the tree is
+    /// sorted again, as a multiple of their area per value after the
+    /// last sort
+    pub fn set_resort_threshold(&mut self, threshold: f64) {
+        self.resort_threshold = threshold;
+    }
+
     fn rebuild(&mut self) {
-        self.values.sort_by(|a, b| {
-            let a: MortonRectangle = a.0.into();
-            let b: MortonRectangle = b.0.into();
-            a.center().cmp(&b.center())
-        });
+        let mut values: Vec<(Rectangle<C>, T)> = self.values.drain(..).filter_map(|v| v).collect();
+        match self.strategy {
+            Strategy::Morton => {
+                sort_by_key(&mut values, |v| MortonRectangle::<C::Key>::from(v.0).center())
+            }
+            Strategy::Hilbert => {
+                sort_by_key(&mut values, |v| HilbertPoint::<C::Key>::from(v.0.center()))
+            }
+            Strategy::SortTileRecursive => sort_tiles(&mut values, |v| v.0),
+        }
         self.nodes.clear();
+        self.free_nodes.clear();
+        self.free_values.clear();
 
-        let (mut start, stop) = (0, self.values.len());
-        while start != stop {
-            let end = std::cmp::min(stop, start + FANOUT_SIZE);
-            let mut rect = self.values[start].0;
-            for i in (start + 1)..end {
-                rect = rect.extend(self.values[i].0);
+        for (i, (rect, value)) in values.into_iter().enumerate() {
+            if i % FANOUT_SIZE == 0 {
+                let start = self.alloc_values();
+                self.nodes.push(Node::empty(PointsAt::Leaf, start));
             }
-            self.nodes.push(Node {
-                rect: rect,
-                leaf: PointsAt::Leaf,
-                start: start,
-                stop: end,
-            });
-            start = end;
+            let leaf = self.nodes.last_mut().unwrap();
+            self.values[leaf.stop] = Some((rect, value));
+            leaf.rect = leaf.rect.extend(rect);
+            leaf.stop += 1;
+            leaf.count += 1;
         }
 
         let (mut start, mut stop) = (0, self.nodes.len());
         while stop - start > 1 {
+            // pad out the level so that each parent owns a full block
+            while self.nodes.len() % FANOUT_SIZE != 0 {
+                self.nodes.push(Node::empty(PointsAt::Leaf, 0));
+            }
+
+            if self.strategy == Strategy::SortTileRecursive {
+                sort_tiles(&mut self.nodes[start..stop], |n| n.rect);
+            }
+
+            let level = self.nodes.len();
             while start != stop {
                 let end = std::cmp::min(stop, start + FANOUT_SIZE);
                 let mut rect = self.nodes[start].rect;
+                let mut count = self.nodes[start].count;
                 for i in (start + 1)..end {
                     rect = rect.extend(self.nodes[i].rect);
+                    count += self.nodes[i].count;
                 }
                 self.nodes.push(Node {
                     rect: rect,
                     leaf: PointsAt::Inner,
                     start: start,
                     stop: end,
+                    count: count,
                 });
                 start = end;
             }
-            start = stop;
+            start = level;
             stop = self.nodes.len();
         }
+        self.sorted_area = Some(self.node_area() / std::cmp::max(self.len(), 1) as f64);
     }
 
-    pub fn clear(&mut self) {
-        self.values.clear();
-        self.nodes.clear();
+    /// the area of every node in the tree added together
+    fn node_area(&self) -> f64 {
+        let mut area = 0.;
+        let mut to_check: Vec<usize> = self.nodes.len().checked_sub(1).into_iter().collect();
+        while let Some(idx) = to_check.pop() {
+            let node = self.nodes[idx];
+            area += node.rect.area();
+            if node.leaf == PointsAt::Inner {
+                to_check.extend(node.start..node.stop);
+            }
+        }
+        area
     }
 
-    pub fn extend<I>(&mut self, iter: I)
-        where I: IntoIterator<Item = (Rectangle, T)>
-    {
-        self.values.extend(iter);
-        self.rebuild();
-    }
+    /// measure how well the nodes of the tree fit their contents
+    pub fn quality(&self) -> Quality {
+        let mut quality = Quality::default();
+        let mut to_check: Vec<Node<C>> = self.nodes.last().cloned().into_iter().collect();
 
-    pub fn query(&self, rect: Rectangle) -> Iter<T> {
-        let mut to_check = vec![];
+        while let Some(node) = to_check.pop() {
+            let children: Vec<Rectangle<C>> = (node.start..node.stop)
+                .map(|i| match node.leaf {
+                    PointsAt::Leaf => self.values[i].as_ref().unwrap().0,
+                    PointsAt::Inner => {
+                        to_check.push(self.nodes[i]);
+                        self.nodes[i].rect
+                    }
+                })
+                .collect();
 
-        if self.nodes.len() >= 1 {
-            let last = self.nodes.len() - 1;
-            if self.nodes[last].rect.intersects(rect) {
-                to_check.push(self.nodes[last])
+            quality.area += node.rect.area();
+            quality.dead_space += node.rect.area() - union_area(&children);
+            if node.leaf == PointsAt::Inner {
+                for (i, &a) in children.iter().enumerate() {
+                    for &b in &children[i + 1..] {
+                        quality.overlap += a.intersection(b).map_or(0., |r| r.area());
+                    }
+                }
             }
         }
+        quality
+    }
 
-        Iter {
-            query: rect,
-            tree: self,
-            to_check: to_check,
+    /// describe the shape of the tree, this walks every node so it
+    /// is meant for debugging rather than every frame
+    pub fn stats(&self) -> Stats {
+        let quality = self.quality();
+        let mut stats = Stats {
+            overlap: quality.overlap,
+            dead_space: quality.dead_space,
+            dead_slots: self.values.len() - self.len(),
+            ..Stats::default()
+        };
+
+        let (mut used, mut room) = (0, 0);
+        let mut level: Vec<usize> = self.nodes.len().checked_sub(1).into_iter().collect();
+        while !level.is_empty() {
+            stats.nodes_per_level.push(level.len());
+            let mut next = vec![];
+            for idx in level {
+                let node = self.nodes[idx];
+                used += node.stop - node.start;
+                room += FANOUT_SIZE;
+                if node.leaf == PointsAt::Inner {
+                    next.extend(node.start..node.stop);
+                }
+            }
+            level = next;
         }
-    }
-}
 
-impl<A> std::iter::FromIterator<(Rectangle, A)> for RTree<A> {
-    fn from_iter<T>(iter: T) -> Self
-        where T: IntoIterator<Item = (Rectangle, A)>
-    {
-        let mut rtree = RTree::new();
-        rtree.extend(iter);
-        rtree
+        stats.depth = stats.nodes_per_level.len();
+        if room != 0 {
+            stats.fill_factor = used as f64 / room as f64;
+        }
+        stats
     }
-}
 
-fn inc(v: &mut usize) -> usize {
-    let old = *v;
-    *v += 1;
-    old
-}
+    /// check the invariants of the tree, every node's rectangle has to
+    /// contain its children and its count has to match them, every leaf
+    /// has to be at the same depth and the leaves have to hold every value
+    /// exactly once. Returns a description of the first problem found
+    pub fn validate(&self) -> Result<(), String> {
+        let root = match self.nodes.len() {
+            0 => {
+                if self.values.iter().any(|v| v.is_some()) {
+                    return Err("tree has no nodes but is holding values".to_string());
+                }
+                return Ok(());
+            }
+            n => n - 1,
+        };
 
-pub struct Iter<'a, T: 'a> {
-    query: Rectangle,
-    tree: &'a RTree<T>,
-    to_check: Vec<Node>,
-}
+        let mut seen_nodes = vec![false; self.nodes.len()];
+        let mut seen_values = vec![false; self.values.len()];
+        let mut value_blocks = vec![];
+        let mut node_blocks = vec![];
+        let mut leaf_depth = None;
+        let mut to_check = vec![(root, 1)];
+        seen_nodes[root] = true;
 
-impl<'a, T: 'a> Iterator for Iter<'a, T> {
-    type Item = (&'a Rectangle, &'a T);
+        while let Some((idx, depth)) = to_check.pop() {
+            let node = self.nodes[idx];
+            if node.start > node.stop || node.stop - node.start > FANOUT_SIZE {
+                return Err(format!("node {} has a bad range {}..{}", idx, node.start, node.stop));
+            }
+            if node.start == node.stop {
+                return Err(format!("node {} is empty", idx));
+            }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        'outer: loop {
-            if let Some(mut last) = self.to_check.pop() {
-                let ret = if last.leaf == PointsAt::Leaf {
-                    let mut ret = None;
-                    while ret.is_none() && last.start != last.stop {
-                        let idx = inc(&mut last.start);
-                        if self.tree.values[idx].0.intersects(self.query) {
-                            ret = Some((&self.tree.values[idx].0, &self.tree.values[idx].1));
+            let mut count = 0;
+            for i in node.start..node.stop {
+                let (rect, c) = match node.leaf {
+                    PointsAt::Leaf => {
+                        if i >= self.values.len() || seen_values[i] {
+                            return Err(format!("value slot {} is out of range or shared", i));
                         }
-                    }
-
-                    if last.start != last.stop {
-                        self.to_check.push(last);
-                    }
-                    ret
-                } else {
-                    let mut idx;
-                    loop {
-                        idx = inc(&mut last.start);
-                        if idx == last.stop {
-                            continue 'outer;
+                        seen_values[i] = true;
+                        match self.values[i] {
+                            Some((rect, _)) => (rect, 1),
+                            None => return Err(format!("node {} covers the empty value slot {}", idx, i)),
                         }
-                        if self.tree.nodes[idx].rect.intersects(self.query) {
-                            break;
+                    }
+                    PointsAt::Inner => {
+                        if i >= root || seen_nodes[i] {
+                            return Err(format!("node slot {} is out of range or shared", i));
                         }
+                        seen_nodes[i] = true;
+                        to_check.push((i, depth + 1));
+                        (self.nodes[i].rect, self.nodes[i].count)
                     }
-
-                    self.to_check.push(last);
-                    self.to_check.push(self.tree.nodes[idx]);
-                    None
                 };
+                if !node.rect.contains_rect(rect) {
+                    return Err(format!("node {} does not contain {:?}", idx, rect));
+                }
+                count += c;
+            }
 
-                if ret.is_some() {
-                    return ret;
+            if count != node.count {
+                return Err(format!("node {} has a count of {} but holds {}", idx, node.count, count));
+            }
+            match node.leaf {
+                PointsAt::Leaf => {
+                    value_blocks.push(node.start);
+                    if *leaf_depth.get_or_insert(depth) != depth {
+                        return Err(format!("leaf {} is at depth {}, expected {}", idx, depth, leaf_depth.unwrap()));
+                    }
                 }
-            } else {
-                return None;
-            };
+                PointsAt::Inner => node_blocks.push(node.start),
+            }
+        }
+
+        // every value has to be in a leaf, anything else is a dead slot
+        if let Some(slot) = (0..self.values.len()).find(|&i| !seen_values[i] && self.values[i].is_some()) {
+            return Err(format!("value slot {} is not in any leaf", slot));
         }
+        if let Some(&start) = self.free_values.iter().find(|start| value_blocks.contains(start)) {
+            return Err(format!("value block {} is free and in use", start));
+        }
+        if let Some(&start) = self.free_nodes.iter().find(|start| node_blocks.contains(start)) {
+            return Err(format!("node block {} is free and in use", start));
+        }
+        Ok(())
     }
-}
 
+    /// get a block of FANOUT_SIZE node slots, the root is kept as the
+    /// last node so it may move
+    fn alloc_nodes(&mut self) -> usize {
+        if let Some(start) = self.free_nodes.pop() {
+            return start;
+        }
+        let root = self.nodes.pop();
+        let start = self.nodes.len();
+        for _ in 0..FANOUT_SIZE {
+            self.nodes.push(Node::empty(PointsAt::Leaf, 0));
+        }
+        if let Some(root) = root {
+            self.nodes.push(root);
+        }
+        start
+    }
 
-#[cfg(test)]
-mod test {
-    use std::i16;
-    use {Rectangle, Point, MortonPoint, RTree};
+    /// get a block of FANOUT_SIZE value slots
+    fn alloc_values(&mut self) -> usize {
+        if let Some(start) = self.free_values.pop() {
+            return start;
+        }
+        let start = self.values.len();
+        self.values.extend((0..FANOUT_SIZE).map(|_| None));
+        start
+    }
 
-    #[test]
-    fn point() {
-        let pt = Point::new(0, 0);
-        assert_eq!(MortonPoint(0xC000_0000), MortonPoint::from(pt));
+    /// recalculate the bounds and count of a node from its children
+    fn refit_node(&mut self, idx: usize) {
+        let node = self.nodes[idx];
+        let (mut rect, mut count) = (Rectangle::empty(), 0);
+        for i in node.start..node.stop {
+            let (r, c) = match node.leaf {
+                PointsAt::Leaf => (self.values[i].as_ref().unwrap().0, 1),
+                PointsAt::Inner => (self.nodes[i].rect, self.nodes[i].count),
+            };
+            rect = rect.extend(r);
+            count += c;
+        }
+        self.nodes[idx].rect = rect;
+        self.nodes[idx].count = count;
+    }
 
-        let pt = Point::new(i16::min_value(), i16::min_value());
-        assert_eq!(MortonPoint(0x0000_0000), MortonPoint::from(pt));
+    /// move the values below a node and recalculate the bounds of every
+    /// node on the way back up, returns the area of those nodes
+    fn refit_below<F>(&mut self, idx: usize, f: &mut F) -> f64
+        where F: FnMut(Rectangle<C>, &T) -> Rectangle<C>
+    {
+        let node = self.nodes[idx];
+        let mut area = 0.;
+        for i in node.start..node.stop {
+            match node.leaf {
+                PointsAt::Leaf => {
+                    let &mut (ref mut rect, ref value) = self.values[i].as_mut().unwrap();
+                    *rect = f(*rect, value);
+                }
+                PointsAt::Inner => area += self.refit_below(i, f),
+            }
+        }
+        self.refit_node(idx);
+        area + self.nodes[idx].rect.area()
+    }
 
-        let pt = Point::new(i16::max_value(), i16::max_value());
-        assert_eq!(MortonPoint(0xFFFF_FFFF), MortonPoint::from(pt));
+    /// add a value to a leaf, if the leaf is full it is split in two
+    /// and the new sibling is returned
+    fn push_value(&mut self, idx: usize, rect: Rectangle<C>, value: T) -> Option<Node<C>> {
+        let node = self.nodes[idx];
+        if node.stop - node.start < FANOUT_SIZE {
+            self.values[node.stop] = Some((rect, value));
+            self.nodes[idx].stop += 1;
+            self.nodes[idx].count += 1;
+            self.nodes[idx].rect = node.rect.extend(rect);
+            return None;
+        }
 
-        let pt = Point::new(i16::max_value(), i16::min_value());
-        assert_eq!(MortonPoint(0x5555_5555), MortonPoint::from(pt));
+        let mut entries: Vec<(Rectangle<C>, T)> = (node.start..node.stop)
+            .map(|i| self.values[i].take().unwrap())
+            .collect();
+        entries.push((rect, value));
+        sort_for_split(&mut entries, |e| e.0);
 
-        let pt = Point::new(i16::min_value(), i16::max_value());
-        assert_eq!(MortonPoint(0xAAAA_AAAA), MortonPoint::from(pt));
+        let mut sibling = Node::empty(PointsAt::Leaf, self.alloc_values());
+        self.nodes[idx] = Node::empty(PointsAt::Leaf, node.start);
+        let half = entries.len() / 2;
+        for (i, entry) in entries.into_iter().enumerate() {
+            let node = if i < half { &mut self.nodes[idx] } else { &mut sibling };
+            node.rect = node.rect.extend(entry.0);
+            self.values[node.stop] = Some(entry);
+            node.stop += 1;
+            node.count += 1;
+        }
+        Some(sibling)
     }
 
-    #[test]
-    fn encode() {
-        for i in i16::min_value()..i16::max_value() {
-            let src = Point::new(i, 0);
-            let out = Point::from(MortonPoint::from(src));
-            assert_eq!(src, out);
+    /// add a child to an inner node, if the node is full it is split in two
+    /// and the new sibling is returned
+    fn push_node(&mut self, idx: usize, child: Node<C>) -> Option<Node<C>> {
+        let node = self.nodes[idx];
+        if node.stop - node.start < FANOUT_SIZE {
+            self.nodes[node.stop] = child;
+            self.nodes[idx].stop += 1;
+            self.nodes[idx].count += child.count;
+            self.nodes[idx].rect = node.rect.extend(child.rect);
+            return None;
         }
 
-        for i in i16::min_value()..i16::max_value() {
-            let src = Point::new(0, i);
-            let out = Point::from(MortonPoint::from(src));
-            assert_eq!(src, out);
+        let mut entries: Vec<Node<C>> = self.nodes[node.start..node.stop].to_vec();
+        entries.push(child);
+        sort_for_split(&mut entries, |n| n.rect);
+
+        let is_root = idx == self.nodes.len() - 1;
+        let mut sibling = Node::empty(PointsAt::Inner, self.alloc_nodes());
+        let idx = if is_root { self.nodes.len() - 1 } else { idx };
+
+        let mut first = Node::empty(PointsAt::Inner, node.start);
+        let half = entries.len() / 2;
+        for (i, entry) in entries.into_iter().enumerate() {
+            let node = if i < half { &mut first } else { &mut sibling };
+            node.rect = node.rect.extend(entry.rect);
+            node.count += entry.count;
+            self.nodes[node.stop] = entry;
+            node.stop += 1;
         }
+        self.nodes[idx] = first;
+        Some(sibling)
     }
 
+    /// find the slot holding `value`, `path` is filled with the nodes
+    /// from the root down to the leaf that holds it
+    fn find(&self, idx: usize, rect: Rectangle<C>, value: &T, path: &mut Vec<usize>) -> Option<usize>
+        where T: PartialEq
+    {
+        let node = self.nodes[idx];
+        if !node.rect.intersects(rect) {
+            return None;
+        }
+
+        path.push(idx);
+        for i in node.start..node.stop {
+            let found = match node.leaf {
+                PointsAt::Leaf => {
+                    let &(r, ref v) = self.values[i].as_ref().unwrap();
+                    if r == rect && v == value { Some(i) } else { None }
+                }
+                PointsAt::Inner => self.find(i, rect, value, path),
+            };
+            if found.is_some() {
+                return found;
+            }
+        }
+        path.pop();
+        None
+    }
+
+    /// remove the value in `slot` from the leaf at the bottom of `path`
+    /// and repair the leaf and its ancestors
+    fn take(&mut self, mut path: Vec<usize>, slot: usize) -> (Rectangle<C>, T) {
+        let mut idx = path.pop().unwrap();
+        let last = self.nodes[idx].stop - 1;
+        self.values.swap(slot, last);
+        let entry = self.values[last].take().unwrap();
+        self.nodes[idx].stop = last;
+
+        loop {
+            let node = self.nodes[idx];
+            let parent = path.pop();
+            if node.start != node.stop {
+                self.refit_node(idx);
+            } else if let Some(parent) = parent {
+                // drop the empty node from its parent and recycle its block
+                let last = self.nodes[parent].stop - 1;
+                self.nodes.swap(idx, last);
+                self.nodes[parent].stop = last;
+                match node.leaf {
+                    PointsAt::Leaf => self.free_values.push(node.start),
+                    PointsAt::Inner => self.free_nodes.push(node.start),
+                }
+            } else {
+                self.clear();
+            }
+
+            match parent {
+                Some(parent) => idx = parent,
+                None => return entry,
+            }
+        }
+    }
+
+    /// remove the values below a node that `remove` picks, only looking
+    /// inside of nodes `prune` accepts. Children left empty are dropped
+    /// and their blocks recycled
+    fn drain_below<P, F>(&mut self, idx: usize, prune: &P, remove: &mut F, removed: &mut Vec<(Rectangle<C>, T)>)
+        where P: Fn(Rectangle<C>) -> bool,
+              F: FnMut(Rectangle<C>, &T) -> bool
+    {
+        let node = self.nodes[idx];
+        let mut stop = node.start;
+        for i in node.start..node.stop {
+            let keep = match node.leaf {
+                PointsAt::Leaf => {
+                    let &(rect, ref value) = self.values[i].as_ref().unwrap();
+                    if remove(rect, value) {
+                        removed.push(self.values[i].take().unwrap());
+                        false
+                    } else {
+                        self.values.swap(stop, i);
+                        true
+                    }
+                }
+                PointsAt::Inner => {
+                    if prune(self.nodes[i].rect) {
+                        self.drain_below(i, prune, remove, removed);
+                    }
+                    let child = self.nodes[i];
+                    if child.start == child.stop {
+                        match child.leaf {
+                            PointsAt::Leaf => self.free_values.push(child.start),
+                            PointsAt::Inner => self.free_nodes.push(child.start),
+                        }
+                        false
+                    } else {
+                        self.nodes.swap(stop, i);
+                        true
+                    }
+                }
+            };
+            if keep {
+                stop += 1;
+            }
+        }
+        self.nodes[idx].stop = stop;
+        self.refit_node(idx);
+    }
+
+    fn drain_where<P, F>(&mut self, prune: P, mut remove: F) -> Vec<(Rectangle<C>, T)>
+        where P: Fn(Rectangle<C>) -> bool,
+              F: FnMut(Rectangle<C>, &T) -> bool
+    {
+        let mut removed = vec![];
+        let root = match self.nodes.len() {
+            0 => return removed,
+            n => n - 1,
+        };
+        if prune(self.nodes[root].rect) {
+            self.drain_below(root, &prune, &mut remove, &mut removed);
+        }
+        if self.nodes[root].start == self.nodes[root].stop {
+            self.clear();
+        }
+        removed
+    }
+
+    /// remove every value that intersects `rect`, the removed values
+    /// are returned in no particular order
+    pub fn remove_in(&mut self, rect: Rectangle<C>) -> Vec<(Rectangle<C>, T)> {
+        self.drain_where(|r| r.intersects(rect), |r, _| r.intersects(rect))
+    }
+
+    /// keep only the values that `keep` returns true for
+    pub fn retain<F>(&mut self, mut keep: F)
+        where F: FnMut(Rectangle<C>, &T) -> bool
+    {
+        self.drain_where(|_| true, |r, v| !keep(r, v));
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.nodes.clear();
+        self.free_nodes.clear();
+        self.free_values.clear();
+        self.sorted_area = None;
+    }
+
+    pub fn extend<I>(&mut self, iter: I)
+        where I: IntoIterator<Item = (Rectangle<C>, T)>
+    {
+        self.values.extend(iter.into_iter().map(Some));
+        self.rebuild();
+    }
+
+    /// add a single value to the tree, only the leaf it lands in
+    /// and the leaf's ancestors are modified
+    pub fn insert(&mut self, rect: Rectangle<C>, value: T) {
+        if self.nodes.is_empty() {
+            let start = self.alloc_values();
+            self.values[start] = Some((rect, value));
+            self.nodes.push(Node {
+                rect: rect,
+                leaf: PointsAt::Leaf,
+                start: start,
+                stop: start + 1,
+                count: 1,
+            });
+            return;
+        }
+
+        // walk down picking the child that needs to grow the least
+        let mut path = vec![self.nodes.len() - 1];
+        loop {
+            let node = self.nodes[*path.last().unwrap()];
+            if node.leaf == PointsAt::Leaf {
+                break;
+            }
+
+            let (mut best, mut best_cost) = (node.start, None);
+            for i in node.start..node.stop {
+                let r = self.nodes[i].rect;
+                let grown = r.extend(rect);
+                let cost = Some((grown.area() - r.area(), margin(grown) - margin(r), r.area()));
+                if best_cost.is_none() || cost < best_cost {
+                    best = i;
+                    best_cost = cost;
+                }
+            }
+            path.push(best);
+        }
+
+        let leaf = path.pop().unwrap();
+        let mut split = self.push_value(leaf, rect, value);
+        while let Some(idx) = path.pop() {
+            // the root can move if a block was allocated
+            let idx = if path.is_empty() { self.nodes.len() - 1 } else { idx };
+            split = match split {
+                Some(node) => {
+                    self.refit_node(idx);
+                    self.push_node(idx, node)
+                }
+                None => {
+                    self.nodes[idx].rect = self.nodes[idx].rect.extend(rect);
+                    self.nodes[idx].count += 1;
+                    None
+                }
+            };
+        }
+
+        // the root was split, grow the tree by a level
+        if let Some(sibling) = split {
+            let start = self.alloc_nodes();
+            let root = self.nodes.pop().unwrap();
+            self.nodes[start] = root;
+            self.nodes[start + 1] = sibling;
+            self.nodes.push(Node {
+                rect: root.rect.extend(sibling.rect),
+                leaf: PointsAt::Inner,
+                start: start,
+                stop: start + 2,
+                count: root.count + sibling.count,
+            });
+        }
+    }
+
+    /// remove a value from the tree, `rect` must match the rectangle it
+    /// was inserted with
+    pub fn remove(&mut self, rect: Rectangle<C>, value: &T) -> Option<T>
+        where T: PartialEq
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut path = vec![];
+        let root = self.nodes.len() - 1;
+        self.find(root, rect, value, &mut path)
+            .map(|slot| self.take(path, slot).1)
+    }
+
+    /// move a value from `old` to `new`, returns false if the value
+    /// was not in the tree
+    pub fn update(&mut self, old: Rectangle<C>, new: Rectangle<C>, value: &T) -> bool
+        where T: PartialEq
+    {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let mut path = vec![];
+        let root = self.nodes.len() - 1;
+        let slot = match self.find(root, old, value, &mut path) {
+            Some(slot) => slot,
+            None => return false,
+        };
+
+        let leaf = self.nodes[*path.last().unwrap()].rect;
+        if leaf.extend(new) == leaf {
+            // still inside of the leaf, only the bounds need fixing
+            self.values[slot].as_mut().unwrap().0 = new;
+            while let Some(idx) = path.pop() {
+                self.refit_node(idx);
+            }
+        } else {
+            let (_, value) = self.take(path, slot);
+            self.insert(new, value);
+        }
+        true
+    }
+
+    /// move every value to the rectangle `f` gives for it. The values
+    /// stay in the leaves they were in and only the bounds of the nodes
+    /// are recalculated, which is much cheaper than a rebuild when values
+    /// move a little at a time. Once the area of the nodes per value has
+    /// grown past the resort threshold the tree is sorted again, returns
+    /// true if it was. A tree built by inserting is measured by its first
+    /// refit instead.
+    pub fn refit<F>(&mut self, mut f: F) -> bool
+        where F: FnMut(Rectangle<C>, &T) -> Rectangle<C>
+    {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let root = self.nodes.len() - 1;
+        let area = self.refit_below(root, &mut f) / std::cmp::max(self.len(), 1) as f64;
+        match self.sorted_area {
+            Some(sorted) if area > sorted * self.resort_threshold => {
+                self.rebuild();
+                true
+            }
+            Some(_) => false,
+            None => {
+                self.sorted_area = Some(area);
+                false
+            }
+        }
+    }
+
+    pub fn query(&self, rect: Rectangle<C>) -> Iter<T, C> {
+        self.query_in(rect, vec![])
+    }
+
+    /// query using `stack` to keep track of the nodes left to look at,
+    /// passing in a `&mut Vec` that is kept between queries saves
+    /// allocating a new one every time
+    pub fn query_in<S>(&self, rect: Rectangle<C>, mut stack: S) -> Iter<T, C, S>
+        where S: BorrowMut<Vec<usize>>
+    {
+        stack.borrow_mut().clear();
+        if let Some(root) = self.nodes.last() {
+            if root.rect.intersects(rect) {
+                stack.borrow_mut().push(self.nodes.len() - 1);
+            }
+        }
+
+        Iter {
+            query: rect,
+            tree: self,
+            to_check: stack,
+            values: 0..0,
+        }
+    }
+
+    /// like `query` but the values can be changed, the rectangles
+    /// cannot as that would leave the nodes above them out of date
+    pub fn query_mut(&mut self, rect: Rectangle<C>) -> IterMut<T, C> {
+        let mut slots = vec![];
+        let mut to_check: Vec<usize> = self.nodes.len().checked_sub(1).into_iter().collect();
+        while let Some(idx) = to_check.pop() {
+            let node = self.nodes[idx];
+            if !node.rect.intersects(rect) {
+                continue;
+            }
+            for i in node.start..node.stop {
+                match node.leaf {
+                    PointsAt::Leaf => {
+                        if self.values[i].as_ref().unwrap().0.intersects(rect) {
+                            slots.push(i);
+                        }
+                    }
+                    PointsAt::Inner => to_check.push(i),
+                }
+            }
+        }
+        slots.sort();
+
+        IterMut {
+            slots: slots.into_iter(),
+            values: &mut self.values,
+            offset: 0,
+        }
+    }
+
+    /// query with each of `rects`, the values found for each
+    /// rectangle are returned in the same order as `rects`
+    pub fn query_many(&self, rects: &[Rectangle<C>]) -> Vec<Vec<(Rectangle<C>, &T)>> {
+        let mut stack = vec![];
+        rects.iter()
+             .map(|&rect| self.query_in(rect, &mut stack).map(|(&r, v)| (r, v)).collect())
+             .collect()
+    }
+
+    /// the same as `query_many` but the queries are spread across threads
+    #[cfg(feature = "parallel")]
+    pub fn par_query_many(&self, rects: &[Rectangle<C>]) -> Vec<Vec<(Rectangle<C>, &T)>>
+        where T: Sync,
+              C: Send + Sync
+    {
+        let chunks: Vec<Vec<Vec<(Rectangle<C>, &T)>>> = rects.par_chunks(64)
+            .map(|chunk| self.query_many(chunk))
+            .collect();
+        chunks.into_iter().flat_map(|c| c).collect()
+    }
+
+    /// call `visit` with every value that intersects `rect` until it
+    /// returns `ControlFlow::Break`. This does not allocate
+    pub fn query_with<F>(&self, rect: Rectangle<C>, visit: F) -> ControlFlow
+        where F: FnMut(&Rectangle<C>, &T) -> ControlFlow
+    {
+        self.search_with(|r| r.intersects(rect), visit)
+    }
+
+    /// call `visit` with every value until it returns `ControlFlow::Break`.
+    /// `prune` is asked about the bounds of each node and the rectangle
+    /// of each value, anything it returns false for is skipped
+    pub fn search_with<P, F>(&self, mut prune: P, mut visit: F) -> ControlFlow
+        where P: FnMut(&Rectangle<C>) -> bool,
+              F: FnMut(&Rectangle<C>, &T) -> ControlFlow
+    {
+        match self.nodes.len() {
+            0 => ControlFlow::Continue,
+            n => self.search_node(n - 1, &mut prune, &mut visit),
+        }
+    }
+
+    fn search_node<P, F>(&self, idx: usize, prune: &mut P, visit: &mut F) -> ControlFlow
+        where P: FnMut(&Rectangle<C>) -> bool,
+              F: FnMut(&Rectangle<C>, &T) -> ControlFlow
+    {
+        let node = self.nodes[idx];
+        if !prune(&node.rect) {
+            return ControlFlow::Continue;
+        }
+
+        for i in node.start..node.stop {
+            let flow = match node.leaf {
+                PointsAt::Leaf => {
+                    let &(ref rect, ref value) = self.values[i].as_ref().unwrap();
+                    if prune(rect) { visit(rect, value) } else { ControlFlow::Continue }
+                }
+                PointsAt::Inner => self.search_node(i, prune, visit),
+            };
+            if flow == ControlFlow::Break {
+                return flow;
+            }
+        }
+        ControlFlow::Continue
+    }
+
+    /// the number of values in the tree
+    pub fn len(&self) -> usize {
+        self.nodes.last().map_or(0, |root| root.count)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// count the values that intersect `rect`. Nodes that are entirely
+    /// inside of `rect` are counted without looking at what they hold
+    pub fn count_in(&self, rect: Rectangle<C>) -> usize {
+        let mut count = 0;
+        let mut to_check: Vec<usize> = self.nodes.len().checked_sub(1).into_iter().collect();
+        while let Some(idx) = to_check.pop() {
+            let node = self.nodes[idx];
+            if !node.rect.intersects(rect) {
+                continue;
+            }
+            if rect.contains_rect(node.rect) {
+                count += node.count;
+                continue;
+            }
+            for i in node.start..node.stop {
+                match node.leaf {
+                    PointsAt::Leaf => {
+                        if self.values[i].as_ref().unwrap().0.intersects(rect) {
+                            count += 1;
+                        }
+                    }
+                    PointsAt::Inner => to_check.push(i),
+                }
+            }
+        }
+        count
+    }
+
+    /// find the `k` values closest to `point`, closest first
+    pub fn nearest(&self, point: Point<C>, k: usize) -> Vec<(Rectangle<C>, &T)> {
+        self.search_nearest(point, k, None)
+    }
+
+    /// find the `k` values closest to `point` that are no further
+    /// than `max_distance` away, closest first
+    pub fn nearest_within(&self, point: Point<C>, k: usize, max_distance: f64) -> Vec<(Rectangle<C>, &T)> {
+        self.search_nearest(point, k, Some(max_distance * max_distance))
+    }
+
+    /// find every pair of values in the tree that touch each other,
+    /// each pair is only returned once
+    pub fn intersecting_pairs(&self, touch: Touch) -> Vec<((Rectangle<C>, &T), (Rectangle<C>, &T))> {
+        let mut pairs = vec![];
+        let mut to_check = vec![];
+        if self.nodes.len() >= 1 {
+            let last = self.nodes.len() - 1;
+            to_check.push((last, last));
+        }
+
+        while let Some((a, b)) = to_check.pop() {
+            let (na, nb) = (self.nodes[a], self.nodes[b]);
+            match (na.leaf, nb.leaf) {
+                (PointsAt::Leaf, PointsAt::Leaf) => {
+                    for i in na.start..na.stop {
+                        // a node paired with itself only checks each pair once
+                        let start = if a == b { i + 1 } else { nb.start };
+                        let &(ra, ref va) = self.values[i].as_ref().unwrap();
+                        for j in start..nb.stop {
+                            let &(rb, ref vb) = self.values[j].as_ref().unwrap();
+                            if touch.test(ra, rb) {
+                                pairs.push(((ra, va), (rb, vb)));
+                            }
+                        }
+                    }
+                }
+                (PointsAt::Inner, PointsAt::Inner) => {
+                    for i in na.start..na.stop {
+                        let start = if a == b { i } else { nb.start };
+                        for j in start..nb.stop {
+                            if touch.test(self.nodes[i].rect, self.nodes[j].rect) {
+                                to_check.push((i, j));
+                            }
+                        }
+                    }
+                }
+                (PointsAt::Inner, PointsAt::Leaf) => {
+                    for i in na.start..na.stop {
+                        if touch.test(self.nodes[i].rect, nb.rect) {
+                            to_check.push((i, b));
+                        }
+                    }
+                }
+                (PointsAt::Leaf, PointsAt::Inner) => {
+                    for j in nb.start..nb.stop {
+                        if touch.test(na.rect, self.nodes[j].rect) {
+                            to_check.push((a, j));
+                        }
+                    }
+                }
+            }
+        }
+        pairs
+    }
+
+    /// find every value that the segment from `from` to `to` touches,
+    /// ordered by where the segment enters them
+    pub fn segment(&self, from: Point<C>, to: Point<C>) -> Vec<(Rectangle<C>, &T)> {
+        let mut hits = vec![];
+        let mut to_check: Vec<Node<C>> = self.nodes.last().cloned().into_iter().collect();
+
+        while let Some(node) = to_check.pop() {
+            if clip(node.rect, from, to).is_none() {
+                continue;
+            }
+
+            for i in node.start..node.stop {
+                match node.leaf {
+                    PointsAt::Leaf => {
+                        let &(rect, ref value) = self.values[i].as_ref().unwrap();
+                        if let Some((enter, _)) = clip(rect, from, to) {
+                            hits.push((enter, rect, value));
+                        }
+                    }
+                    PointsAt::Inner => to_check.push(self.nodes[i]),
+                }
+            }
+        }
+
+        hits.sort_by(|a, b| a.0.cmp(&b.0));
+        hits.into_iter().map(|(_, rect, value)| (rect, value)).collect()
+    }
+
+    /// cast a ray from `origin` along `direction` for `max_len` units,
+    /// returns the values it touches ordered by where the ray enters them
+    pub fn raycast(&self, origin: Point<C>, direction: Point<C>, max_len: f64) -> Vec<(Rectangle<C>, &T)> {
+        let ray = Ray::new(origin, direction, max_len);
+        let mut hits = vec![];
+        let mut to_check: Vec<Node<C>> = self.nodes.last().cloned().into_iter().collect();
+
+        while let Some(node) = to_check.pop() {
+            if ray.enter(node.rect).is_none() {
+                continue;
+            }
+
+            for i in node.start..node.stop {
+                match node.leaf {
+                    PointsAt::Leaf => {
+                        let &(rect, ref value) = self.values[i].as_ref().unwrap();
+                        if let Some(enter) = ray.enter(rect) {
+                            hits.push((enter, rect, value));
+                        }
+                    }
+                    PointsAt::Inner => to_check.push(self.nodes[i]),
+                }
+            }
+        }
+
+        hits.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        hits.into_iter().map(|(_, rect, value)| (rect, value)).collect()
+    }
+
+    /// best first search, nodes and values are visited in order of their
+    /// distance so a value is only returned once nothing can be closer
+    fn search_nearest(&self, point: Point<C>, k: usize, max: Option<f64>) -> Vec<(Rectangle<C>, &T)> {
+        let mut out = Vec::with_capacity(k);
+        let mut heap = BinaryHeap::new();
+
+        let in_range = |dist: f64| max.map(|max| dist <= max).unwrap_or(true);
+        if let Some(root) = self.nodes.last() {
+            let dist = distance2(root.rect, point);
+            if in_range(dist) {
+                heap.push(Candidate {
+                    dist: dist,
+                    leaf: PointsAt::Inner,
+                    idx: self.nodes.len() - 1,
+                });
+            }
+        }
+
+        while out.len() < k {
+            let next = match heap.pop() {
+                Some(next) => next,
+                None => break,
+            };
+
+            if next.leaf == PointsAt::Leaf {
+                let &(rect, ref value) = self.values[next.idx].as_ref().unwrap();
+                out.push((rect, value));
+                continue;
+            }
+
+            let node = self.nodes[next.idx];
+            for i in node.start..node.stop {
+                let rect = match node.leaf {
+                    PointsAt::Leaf => self.values[i].as_ref().unwrap().0,
+                    PointsAt::Inner => self.nodes[i].rect,
+                };
+                let dist = distance2(rect, point);
+                if in_range(dist) {
+                    heap.push(Candidate {
+                        dist: dist,
+                        leaf: node.leaf,
+                        idx: i,
+                    });
+                }
+            }
+        }
+        out
+    }
+}
+
+impl<A, C: Coordinate> std::iter::FromIterator<(Rectangle<C>, A)> for RTree<A, C> {
+    fn from_iter<T>(iter: T) -> Self
+        where T: IntoIterator<Item = (Rectangle<C>, A)>
+    {
+        let mut rtree = RTree::new();
+        rtree.extend(iter);
+        rtree
+    }
+}
+
+/// a ray as `origin + t * direction` for `t` from 0 to `t_max`. It is
+/// kept in f64 so rounding an end point to a coordinate does not bend it
+#[derive(Debug, Copy, Clone)]
+struct Ray {
+    origin: (f64, f64),
+    direction: (f64, f64),
+    t_max: f64,
+}
+
+impl Ray {
+    /// a ray from `origin` along `direction` for `max_len` units, a ray
+    /// with no direction only covers its origin
+    fn new<C: Coordinate>(origin: Point<C>, direction: Point<C>, max_len: f64) -> Ray {
+        let (dx, dy) = (direction.x.to_f64(), direction.y.to_f64());
+        let len = (dx * dx + dy * dy).sqrt();
+        Ray {
+            origin: (origin.x.to_f64(), origin.y.to_f64()),
+            direction: (dx, dy),
+            t_max: if len == 0. { 0. } else { max_len / len },
+        }
+    }
+
+    /// the far end of the ray
+    fn end(&self) -> (f64, f64) {
+        let along = |o: f64, d: f64| if d == 0. { o } else { o + d * self.t_max };
+        (along(self.origin.0, self.direction.0), along(self.origin.1, self.direction.1))
+    }
+
+    /// how far along the ray it enters the rectangle, touching an edge
+    /// counts as a hit
+    fn enter<C: Coordinate>(&self, r: Rectangle<C>) -> Option<f64> {
+        let (mut enter, mut exit) = (0., self.t_max);
+        let axes = [(self.origin.0, self.direction.0, r.min.x.to_f64(), r.max.x.to_f64()),
+                    (self.origin.1, self.direction.1, r.min.y.to_f64(), r.max.y.to_f64())];
+        for &(o, d, min, max) in &axes {
+            if d == 0. {
+                if o < min || o > max {
+                    return None;
+                }
+                continue;
+            }
+
+            let (a, b) = ((min - o) / d, (max - o) / d);
+            let (near, far) = if d > 0. { (a, b) } else { (b, a) };
+            enter = if near > enter { near } else { enter };
+            exit = if far < exit { far } else { exit };
+        }
+
+        if enter <= exit { Some(enter) } else { None }
+    }
+}
+
+/// the squared distance from the point to the closest point in the rectangle
+fn distance2<C: Coordinate>(r: Rectangle<C>, p: Point<C>) -> f64 {
+    let axis = |min: C, max: C, p: C| {
+        let (min, max, p) = (min.to_f64(), max.to_f64(), p.to_f64());
+        (min - p).max(p - max).max(0.)
+    };
+    let dx = axis(r.min.x, r.max.x, p.x);
+    let dy = axis(r.min.y, r.max.y, p.y);
+    dx * dx + dy * dy
+}
+
+/// `num / den` of the way along a segment, `den` is always positive.
+/// Integer coordinates are kept as an exact fraction so they compare
+/// exactly, floats compare as f64 with NaN after everything else.
+#[derive(Debug, Copy, Clone)]
+enum Fraction {
+    Exact(i128, i128),
+    Float(f64, f64),
+}
+
+impl Fraction {
+    /// how far `at` is along the line from `from` to `to`
+    fn along<C: Coordinate>(at: C, from: C, to: C) -> Fraction {
+        match (at.to_i64(), from.to_i64(), to.to_i64()) {
+            (Some(at), Some(from), Some(to)) => {
+                let (num, den) = ((at - from) as i128, (to - from) as i128);
+                if den < 0 { Fraction::Exact(-num, -den) } else { Fraction::Exact(num, den) }
+            }
+            _ => {
+                let (num, den) = (at.to_f64() - from.to_f64(), to.to_f64() - from.to_f64());
+                if den < 0. { Fraction::Float(-num, -den) } else { Fraction::Float(num, den) }
+            }
+        }
+    }
+
+    fn to_f64(self) -> (f64, f64) {
+        match self {
+            Fraction::Exact(num, den) => (num as f64, den as f64),
+            Fraction::Float(num, den) => (num, den),
+        }
+    }
+}
+
+impl PartialEq for Fraction {
+    fn eq(&self, other: &Fraction) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Fraction {}
+
+impl Ord for Fraction {
+    fn cmp(&self, other: &Fraction) -> Ordering {
+        if let (Fraction::Exact(a, b), Fraction::Exact(c, d)) = (*self, *other) {
+            return (a * d).cmp(&(c * b));
+        }
+
+        let ((a, b), (c, d)) = (self.to_f64(), other.to_f64());
+        let (x, y) = (a * d, c * b);
+        match (x.is_nan(), y.is_nan()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => x.partial_cmp(&y).unwrap(),
+        }
+    }
+}
+
+impl PartialOrd for Fraction {
+    fn partial_cmp(&self, other: &Fraction) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// find how far along the segment `from -> to` it enters and leaves
+/// the rectangle, touching an edge counts as a hit
+fn clip<C: Coordinate>(r: Rectangle<C>, from: Point<C>, to: Point<C>) -> Option<(Fraction, Fraction)> {
+    let mut enter = Fraction::Exact(0, 1);
+    let mut exit = Fraction::Exact(1, 1);
+
+    let axes = [(from.x, to.x, r.min.x, r.max.x), (from.y, to.y, r.min.y, r.max.y)];
+    for &(from, to, min, max) in &axes {
+        if from == to {
+            if from < min || from > max {
+                return None;
+            }
+            continue;
+        }
+
+        let (near, far) = if to > from { (min, max) } else { (max, min) };
+        enter = std::cmp::max(enter, Fraction::along(near, from, to));
+        exit = std::cmp::min(exit, Fraction::along(far, from, to));
+    }
+
+    if enter <= exit { Some((enter, exit)) } else { None }
+}
+
+/// an entry in the nearest neighbour search, `idx` is a value slot if
+/// `leaf` is `Leaf` otherwise it is a node. Ordered so the closest entry
+/// is on top of the heap.
+#[derive(Debug, Copy, Clone)]
+struct Candidate {
+    dist: f64,
+    leaf: PointsAt,
+    idx: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Candidate) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Candidate) -> Ordering {
+        other.dist
+            .partial_cmp(&self.dist)
+            .unwrap_or(Ordering::Equal)
+            .then((other.leaf, other.idx).cmp(&(self.leaf, self.idx)))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Candidate) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+pub struct Iter<'a, T: 'a, C: 'a, S = Vec<usize>> {
+    query: Rectangle<C>,
+    tree: &'a RTree<T, C>,
+    /// the nodes that still need to be looked at
+    to_check: S,
+    /// the values left in the leaf being looked at
+    values: std::ops::Range<usize>,
+}
+
+impl<'a, T: 'a, C, S> Iterator for Iter<'a, T, C, S>
+    where C: Coordinate + 'a,
+          S: BorrowMut<Vec<usize>>
+{
+    type Item = (&'a Rectangle<C>, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            while let Some(idx) = self.values.next() {
+                let &(ref rect, ref value) = self.tree.values[idx].as_ref().unwrap();
+                if rect.intersects(self.query) {
+                    return Some((rect, value));
+                }
+            }
+
+            let node = match self.to_check.borrow_mut().pop() {
+                Some(idx) => self.tree.nodes[idx],
+                None => return None,
+            };
+            match node.leaf {
+                PointsAt::Leaf => self.values = node.start..node.stop,
+                PointsAt::Inner => {
+                    for idx in node.start..node.stop {
+                        if self.tree.nodes[idx].rect.intersects(self.query) {
+                            self.to_check.borrow_mut().push(idx);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+pub struct IterMut<'a, T: 'a, C: 'a> {
+    /// the slots that matched, in order
+    slots: std::vec::IntoIter<usize>,
+    /// the slots after the last one returned, starting with `offset`
+    values: &'a mut [Option<(Rectangle<C>, T)>],
+    offset: usize,
+}
+
+impl<'a, T: 'a, C: 'a> Iterator for IterMut<'a, T, C> {
+    type Item = (&'a Rectangle<C>, &'a mut T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let idx = match self.slots.next() {
+            Some(idx) => idx,
+            None => return None,
+        };
+
+        let values = std::mem::replace(&mut self.values, &mut []);
+        let (slot, rest) = values[idx - self.offset..].split_first_mut().unwrap();
+        self.values = rest;
+        self.offset = idx + 1;
+
+        let &mut (ref rect, ref mut value) = slot.as_mut().unwrap();
+        Some((rect, value))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std;
+    use std::i16;
+    use {Rectangle, Point, MortonPoint, RTree};
+    use super::HilbertPoint;
+    use super::{Touch, Strategy, Quality, Stats, ControlFlow, PointsAt};
+    use quickcheck::{QuickCheck, Testable};
+
+    fn quickcheck<A: Testable>(a: A) {
+        QuickCheck::new().tests(1000).quickcheck(a)
+    }
+
+    /// a rectangle with min and max in the right order
+    fn rect(ax: i16, ay: i16, bx: i16, by: i16) -> Rectangle {
+        Rectangle {
+            min: Point::new(ax.min(bx), ay.min(by)),
+            max: Point::new(ax.max(bx), ay.max(by)),
+        }
+    }
+
+    fn clamp(x: i32) -> i16 {
+        x.max(i16::min_value() as i32).min(i16::max_value() as i32) as i16
+    }
+
+    #[test]
+    fn point() {
+        let pt: Point = Point::new(0, 0);
+        assert_eq!(MortonPoint(0xC000_0000), MortonPoint::from(pt));
+
+        let pt = Point::new(i16::min_value(), i16::min_value());
+        assert_eq!(MortonPoint(0x0000_0000), MortonPoint::from(pt));
+
+        let pt = Point::new(i16::max_value(), i16::max_value());
+        assert_eq!(MortonPoint(0xFFFF_FFFF), MortonPoint::from(pt));
+
+        let pt = Point::new(i16::max_value(), i16::min_value());
+        assert_eq!(MortonPoint(0x5555_5555), MortonPoint::from(pt));
+
+        let pt = Point::new(i16::min_value(), i16::max_value());
+        assert_eq!(MortonPoint(0xAAAA_AAAA), MortonPoint::from(pt));
+    }
+
+    #[test]
+    fn encode() {
+        for i in i16::min_value()..i16::max_value() {
+            let src = Point::new(i, 0);
+            let out = Point::from(MortonPoint::from(src));
+            assert_eq!(src, out);
+        }
+
+        for i in i16::min_value()..i16::max_value() {
+            let src = Point::new(0, i);
+            let out = Point::from(MortonPoint::from(src));
+            assert_eq!(src, out);
+        }
+    }
+
+
+    #[test]
+    fn hilbert_point() {
+        let pt = Point::new(i16::min_value(), i16::min_value());
+        assert_eq!(HilbertPoint(0x0000_0000), HilbertPoint::from(pt));
+
+        let pt = Point::new(i16::max_value(), i16::min_value());
+        assert_eq!(HilbertPoint(0xFFFF_FFFF), HilbertPoint::from(pt));
+
+        // the curve walks the quadrants in order
+        assert_eq!(0, HilbertPoint::from(Point::<i16>::new(-1, -1)).0 >> 30);
+        assert_eq!(1, HilbertPoint::from(Point::<i16>::new(-1, 0)).0 >> 30);
+        assert_eq!(2, HilbertPoint::from(Point::<i16>::new(0, 0)).0 >> 30);
+        assert_eq!(3, HilbertPoint::from(Point::<i16>::new(0, -1)).0 >> 30);
+    }
+
+    #[test]
+    fn hilbert_encode() {
+        for i in i16::min_value()..i16::max_value() {
+            let src = Point::new(i, 0);
+            let out = Point::from(HilbertPoint::from(src));
+            assert_eq!(src, out);
+        }
+
+        for i in i16::min_value()..i16::max_value() {
+            let src = Point::new(0, i);
+            let out = Point::from(HilbertPoint::from(src));
+            assert_eq!(src, out);
+        }
+
+        // every step along the curve moves to a neighbouring point
+        let mut last: Point = Point::from(HilbertPoint(0));
+        for i in 1..(1 << 20) {
+            let next = Point::from(HilbertPoint(i));
+            let dist = (last.x as i32 - next.x as i32).abs() + (last.y as i32 - next.y as i32).abs();
+            assert_eq!(1, dist);
+            last = next;
+        }
+    }
+
+    #[test]
+    fn encode_wide() {
+        let pt: Point<i32> = Point::new(0, 0);
+        assert_eq!(MortonPoint(0xC000_0000_0000_0000), MortonPoint::from(pt));
+        let pt = Point::new(i32::max_value(), i32::min_value());
+        assert_eq!(MortonPoint(0x5555_5555_5555_5555), MortonPoint::from(pt));
+
+        for i in (i32::min_value() / 4099..i32::max_value() / 4099).map(|i| i * 4099) {
+            for &src in &[Point::new(i, 0), Point::new(-7, i), Point::new(i, !i)] {
+                assert_eq!(src, Point::from(MortonPoint::from(src)));
+                assert_eq!(src, Point::from(HilbertPoint::from(src)));
+            }
+        }
+
+        for &f in &[0., -0.5, 1e-40, 3.25, -1e30, ::std::f32::MAX, ::std::f32::MIN] {
+            let src: Point<f32> = Point::new(f, -f);
+            assert_eq!(src, Point::from(MortonPoint::from(src)));
+            assert_eq!(src, Point::from(HilbertPoint::from(src)));
+        }
+    }
+
+    #[test]
+    fn insert() {
+        let mut keys = Vec::new();
+
+        for x in -10..10 {
+            for y in -10..10 {
+                let rect = Rectangle {
+                    min: Point::new(x * 10, y * 10),
+                    max: Point::new(x * 10 + 5, y * 10 + 5),
+                };
+                keys.push((rect, (x, y)));
+            }
+        }
+
+        let tree: RTree<_> = keys.drain(..).collect();
+
+        assert_eq!(400,
+                   tree.query(Rectangle {
+                           max: Point { x: 100, y: 100 },
+                           min: Point { x: -100, y: -100 },
+                       })
+                       .count());
+
+        for x in -10..10 {
+            for y in -10..10 {
+                assert_eq!(1,
+                           tree.query(Rectangle {
+                                   min: Point::new(x * 10, y * 10),
+                                   max: Point::new(x * 10 + 5, y * 10 + 5),
+                               })
+                               .count());
+            }
+        }
+
+        for x in -5..5 {
+            for y in -5..5 {
+                assert_eq!(4,
+                           tree.query(Rectangle {
+                                   min: Point::new(x * 20, y * 20),
+                                   max: Point::new(x * 20 + 15, y * 20 + 15),
+                               })
+                               .count());
+            }
+        }
+
+        for x in -2..2 {
+            for y in -2..2 {
+                assert_eq!(25,
+                           tree.query(Rectangle {
+                                   min: Point::new(x * 50, y * 50),
+                                   max: Point::new(x * 50 + 45, y * 50 + 45),
+                               })
+                               .count());
+            }
+        }
+
+        for x in -1..1 {
+            for y in -1..1 {
+                assert_eq!(100,
+                           tree.query(Rectangle {
+                                   min: Point::new(x * 100, y * 100),
+                                   max: Point::new(x * 100 + 95, y * 100 + 95),
+                               })
+                               .count());
+            }
+        }
+    }
+
+    #[test]
+    fn insert_close() {
+        let mut keys = Vec::new();
+
+        for x in -100..100 {
+            for y in -100..100 {
+                let rect = Rectangle {
+                    min: Point::new(x, y),
+                    max: Point::new(x, y),
+                };
+                keys.push((rect, (x, y)));
+            }
+        }
+
+        let tree: RTree<_> = keys.drain(..).collect();
+
+        let all = Rectangle {
+            min: Point::new(-100, -100),
+            max: Point::new(100, 100),
+        };
+
+        assert_eq!(tree.query(all.into()).count(), 40_000);
+    }
+
+    fn grid(x: i16, y: i16) -> Rectangle {
+        Rectangle {
+            min: Point::new(x * 10, y * 10),
+            max: Point::new(x * 10 + 5, y * 10 + 5),
+        }
+    }
+
+    #[test]
+    fn insert_incremental() {
+        let mut tree = RTree::new();
+        for x in -50..50 {
+            for y in -50..50 {
+                tree.insert(grid(x, y), (x, y));
+            }
+        }
+
+        let all = Rectangle {
+            min: Point::new(-500, -500),
+            max: Point::new(500, 500),
+        };
+        assert_eq!(10_000, tree.query(all).count());
+
+        for x in -50..50 {
+            for y in -50..50 {
+                let found: Vec<_> = tree.query(grid(x, y)).map(|(_, &v)| v).collect();
+                assert_eq!(found, vec![(x, y)]);
+            }
+        }
+    }
+
+    #[test]
+    fn query_with() {
+        let tree: RTree<(i16, i16)> = (-20..20)
+            .flat_map(|x| (-20..20).map(move |y| (grid(x, y), (x, y))))
+            .collect();
+
+        let query = Rectangle {
+            min: Point::new(-25, -25),
+            max: Point::new(25, 25),
+        };
+        let mut found = vec![];
+        let flow = tree.query_with(query, |_, &v| {
+            found.push(v);
+            ControlFlow::Continue
+        });
+        let mut expected: Vec<_> = tree.query(query).map(|(_, &v)| v).collect();
+        found.sort();
+        expected.sort();
+        assert_eq!(ControlFlow::Continue, flow);
+        assert_eq!(36, found.len());
+        assert_eq!(expected, found);
+
+        // stop after the first few
+        let mut count = 0;
+        let flow = tree.query_with(query, |_, _| {
+            count += 1;
+            if count == 3 { ControlFlow::Break } else { ControlFlow::Continue }
+        });
+        assert_eq!(ControlFlow::Break, flow);
+        assert_eq!(3, count);
+
+        // reusing a stack gives the same results as a plain query
+        let mut stack = Vec::new();
+        for x in -20..20 {
+            let query = grid(x, x).expand_by(10);
+            let a: Vec<_> = tree.query_in(query, &mut stack).map(|(_, &v)| v).collect();
+            let b: Vec<_> = tree.query(query).map(|(_, &v)| v).collect();
+            assert_eq!(a, b);
+        }
+    }
+
+    #[test]
+    fn query_mut() {
+        let mut tree: RTree<(i16, i16, u32)> = (-20..20)
+            .flat_map(|x| (-20..20).map(move |y| (grid(x, y), (x, y, 0))))
+            .collect();
+
+        for x in -20..20 {
+            let query = grid(x, 0).expand_by(10);
+            for (_, v) in tree.query_mut(query) {
+                v.2 += 1;
+            }
+        }
+
+        // each cell was hit once for every query that reached it
+        for (&rect, &(x, y, hits)) in tree.query(grid(0, 0).expand_by(1000)) {
+            assert_eq!(grid(x, y), rect);
+            let expected = if y.abs() > 1 { 0 } else if x == -20 || x == 19 { 2 } else { 3 };
+            assert_eq!(expected, hits);
+        }
+        assert_eq!(0, tree.query_mut(grid(100, 100)).count());
+        assert_eq!(0, RTree::<()>::new().query_mut(grid(0, 0)).count());
+    }
+
+    #[test]
+    fn query_many() {
+        // big enough to be sorted across threads
+        for &strategy in &[Strategy::Morton, Strategy::Hilbert, Strategy::SortTileRecursive] {
+            let mut tree = RTree::with_strategy(strategy);
+            tree.extend((-50..50).flat_map(|x| (-50..50).map(move |y| (grid(x, y), (x, y)))));
+
+            let rects: Vec<_> = (-50..50).map(|x| grid(x, x / 2).expand_by(x.abs())).collect();
+            let found = tree.query_many(&rects);
+            assert_eq!(rects.len(), found.len());
+            for (&rect, found) in rects.iter().zip(found.iter()) {
+                let expected: Vec<_> = tree.query(rect).map(|(&r, v)| (r, v)).collect();
+                assert_eq!(&expected, found);
+                assert!(found.iter().any(|&(r, _)| r == grid(r.min.x / 10, r.min.y / 10)));
+            }
+
+            #[cfg(feature = "parallel")]
+            assert_eq!(found, tree.par_query_many(&rects));
+        }
+    }
+
+    #[test]
+    fn search_with() {
+        let tree: RTree<(i16, i16)> = (-20..20)
+            .flat_map(|x| (-20..20).map(move |y| (grid(x, y), (x, y))))
+            .collect();
+
+        // everything within a circle rather than a rectangle
+        let center = Point::new(2, 2);
+        let mut found = vec![];
+        tree.search_with(|r| r.distance_to_point(center) <= 11., |_, &v| {
+            found.push(v);
+            ControlFlow::Continue
+        });
+        found.sort();
+        assert_eq!(vec![(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 0), (0, 1), (1, -1), (1, 0)], found);
+
+        let mut visited = 0;
+        tree.search_with(|_| false, |_, _| {
+            visited += 1;
+            ControlFlow::Continue
+        });
+        assert_eq!(0, visited);
+        assert_eq!(ControlFlow::Continue, RTree::<()>::new().search_with(|_| true, |_, _| ControlFlow::Break));
+    }
+
+    #[test]
+    fn count_in() {
+        let mut tree = RTree::new();
+        assert_eq!(0, tree.len());
+        assert_eq!(0, tree.count_in(grid(0, 0)));
+
+        for x in -20..20 {
+            for y in -20..20 {
+                tree.insert(grid(x, y), (x, y));
+            }
+        }
+        assert_eq!(1600, tree.len());
+
+        let check = |tree: &RTree<(i16, i16)>| {
+            for x in -22..22 {
+                for &size in &[0, 7, 33, 150] {
+                    let query = grid(x, -x).expand_by(size);
+                    assert_eq!(tree.query(query).count(), tree.count_in(query));
+                }
+            }
+        };
+        check(&tree);
+
+        for x in -20..0 {
+            for y in -20..20 {
+                tree.remove(grid(x, y), &(x, y));
+            }
+        }
+        assert_eq!(800, tree.len());
+        check(&tree);
+
+        tree.refit(|r, _| r.translate(Point::new(3, 0)));
+        check(&tree);
+        tree.set_strategy(Strategy::SortTileRecursive);
+        assert_eq!(800, tree.len());
+        check(&tree);
+    }
+
+    #[test]
+    fn remove() {
+        let mut keys = Vec::new();
+        for x in -20..20 {
+            for y in -20..20 {
+                keys.push((grid(x, y), (x, y)));
+            }
+        }
+        let mut tree: RTree<_> = keys.drain(..).collect();
+
+        assert_eq!(None, tree.remove(grid(0, 0), &(1, 1)));
+        assert_eq!(None, tree.remove(grid(1, 1), &(0, 0)));
+
+        for x in -20..20 {
+            for y in -20..0 {
+                assert_eq!(Some((x, y)), tree.remove(grid(x, y), &(x, y)));
+            }
+        }
+
+        for x in -20..20 {
+            for y in -20..20 {
+                let expected = if y < 0 { 0 } else { 1 };
+                assert_eq!(expected, tree.query(grid(x, y)).count());
+            }
+        }
+
+        for x in -20..20 {
+            for y in 0..20 {
+                assert_eq!(Some((x, y)), tree.remove(grid(x, y), &(x, y)));
+            }
+        }
+
+        let all = Rectangle {
+            min: Point::new(-200, -200),
+            max: Point::new(200, 200),
+        };
+        assert_eq!(0, tree.query(all).count());
+
+        tree.insert(grid(3, 3), (3, 3));
+        assert_eq!(1, tree.query(all).count());
+    }
+
+    #[test]
+    fn stats() {
+        let mut tree = RTree::new();
+        assert_eq!(Stats::default(), tree.stats());
+        assert_eq!(Ok(()), tree.validate());
+
+        // 4096 values fill 512 leaves, 64 inner nodes, 8 and then the root
+        tree.extend((-32..32).flat_map(|x| (-32..32).map(move |y| (grid(x, y), (x, y)))));
+        let stats = tree.stats();
+        assert_eq!(4, stats.depth);
+        assert_eq!(vec![1, 8, 64, 512], stats.nodes_per_level);
+        assert_eq!(1., stats.fill_factor);
+        assert_eq!(0, stats.dead_slots);
+        assert_eq!(tree.quality().overlap, stats.overlap);
+        assert_eq!(Ok(()), tree.validate());
+
+        for x in -32..0 {
+            for y in -32..32 {
+                tree.remove(grid(x, y), &(x, y));
+            }
+        }
+        let stats = tree.stats();
+        assert_eq!(2048, stats.dead_slots);
+        assert!(stats.fill_factor < 1.);
+        assert_eq!(Ok(()), tree.validate());
+    }
 
     #[test]
-    fn insert() {
+    fn validate() {
+        let build = || {
+            let mut tree = RTree::new();
+            for x in -10..10 {
+                for y in -10..10 {
+                    tree.insert(grid(x, y), (x, y));
+                }
+            }
+            assert_eq!(Ok(()), tree.validate());
+            tree
+        };
+        let root = |tree: &RTree<(i16, i16)>| tree.nodes.len() - 1;
+
+        let mut tree = build();
+        let r = root(&tree);
+        tree.nodes[r].rect.max.x -= 10;
+        assert!(tree.validate().is_err());
+
+        let mut tree = build();
+        let r = root(&tree);
+        tree.nodes[r].count += 1;
+        assert!(tree.validate().is_err());
+
+        // a value that no leaf points at
+        let mut tree = build();
+        tree.values.push(Some((grid(0, 0), (0, 0))));
+        assert!(tree.validate().is_err());
+
+        // a leaf that points at an empty slot
+        let mut tree = build();
+        let leaf = (0..tree.nodes.len()).find(|&i| tree.nodes[i].leaf == PointsAt::Leaf && tree.nodes[i].count != 0).unwrap();
+        let slot = tree.nodes[leaf].start;
+        tree.values[slot] = None;
+        assert!(tree.validate().is_err());
+
+        let mut tree = build();
+        let start = tree.nodes[leaf].start;
+        tree.free_values.push(start);
+        assert!(tree.validate().is_err());
+    }
+
+    #[test]
+    fn remove_in() {
+        for &strategy in &[Strategy::Morton, Strategy::Hilbert, Strategy::SortTileRecursive] {
+            let mut tree = RTree::with_strategy(strategy);
+            tree.extend((-20..20).flat_map(|x| (-20..20).map(move |y| (grid(x, y), (x, y)))));
+
+            // a blast in the middle
+            let blast = grid(0, 0).expand_by(52);
+            let mut removed: Vec<_> = tree.remove_in(blast).into_iter().map(|(_, v)| v).collect();
+            removed.sort();
+            let mut expected = vec![];
+            for x in -5..6 {
+                for y in -5..6 {
+                    expected.push((x, y));
+                }
+            }
+            assert_eq!(expected, removed);
+            assert_eq!(1600 - 121, tree.len());
+            assert_eq!(0, tree.query(blast).count());
+            assert_eq!(0, tree.count_in(blast));
+            assert!(tree.remove_in(blast).is_empty());
+
+            // the rest is still there and the tree can keep growing
+            for x in -20..20 {
+                for y in -20..20 {
+                    let found: Vec<_> = tree.query(grid(x, y)).map(|(_, &v)| v).collect();
+                    if x < -5 || x > 5 || y < -5 || y > 5 {
+                        assert_eq!(found, vec![(x, y)]);
+                    } else {
+                        assert!(found.is_empty());
+                        tree.insert(grid(x, y), (x, y));
+                    }
+                }
+            }
+            assert_eq!(1600, tree.len());
+
+            let all = grid(0, 0).expand_by(1000);
+            assert_eq!(1600, tree.remove_in(all).len());
+            assert_eq!(0, tree.len());
+            assert!(tree.nodes.is_empty());
+        }
+    }
+
+    #[test]
+    fn retain() {
+        let mut tree = RTree::new();
+        for x in -20..20 {
+            for y in -20..20 {
+                tree.insert(grid(x, y), (x, y));
+            }
+        }
+
+        tree.retain(|_, &(x, y)| (x + y) % 3 == 0);
+        let mut left: Vec<_> = tree.query(grid(0, 0).expand_by(1000)).map(|(_, &v)| v).collect();
+        left.sort();
+        let mut expected = vec![];
+        for x in -20..20 {
+            for y in -20..20 {
+                if (x + y) % 3 == 0 {
+                    expected.push((x, y));
+                }
+            }
+        }
+        assert_eq!(expected, left);
+        assert_eq!(expected.len(), tree.len());
+        for x in -20..20 {
+            let query = grid(x, x).expand_by(25);
+            assert_eq!(tree.query(query).count(), tree.count_in(query));
+        }
+
+        tree.retain(|r, _| r.min.x >= 0);
+        assert!(tree.query(grid(0, 0).expand_by(1000)).all(|(r, _)| r.min.x >= 0));
+        tree.retain(|_, _| false);
+        assert_eq!(0, tree.len());
+    }
+
+    #[test]
+    fn update() {
         let mut keys = Vec::new();
+        for x in -20..20 {
+            for y in -20..20 {
+                keys.push((grid(x, y), (x, y)));
+            }
+        }
+        let mut tree: RTree<_> = keys.drain(..).collect();
 
-        for x in -10..10 {
-            for y in -10..10 {
+        assert!(!tree.update(grid(0, 0), grid(1, 1), &(1, 1)));
+
+        // walk everything across the grid one step at a time
+        for step in 0..5 {
+            for x in -20..20 {
+                for y in -20..20 {
+                    let (from, to) = (grid(x + step, y), grid(x + step + 1, y));
+                    assert!(tree.update(from, to, &(x, y)));
+                }
+            }
+        }
+
+        for x in -20..20 {
+            for y in -20..20 {
+                let found: Vec<_> = tree.query(grid(x + 5, y)).map(|(_, &v)| v).collect();
+                assert_eq!(found, vec![(x, y)]);
+            }
+        }
+
+        // small moves stay inside of the leaf
+        for x in -20..20 {
+            for y in -20..20 {
+                let from = grid(x + 5, y);
+                let mut to = from;
+                to.max.x -= 1;
+                assert!(tree.update(from, to, &(x, y)));
+                assert_eq!(Some((x, y)), tree.remove(to, &(x, y)));
+            }
+        }
+    }
+
+    /// move neighbours in -20..20 far apart
+    fn scatter(x: i16) -> i16 {
+        (x + 20) * 7 % 40 - 20
+    }
+
+    #[test]
+    fn refit() {
+        for &strategy in &[Strategy::Morton, Strategy::Hilbert, Strategy::SortTileRecursive] {
+            let mut tree = RTree::with_strategy(strategy);
+            for x in -20..20 {
+                for y in -20..20 {
+                    tree.insert(grid(x, y), (x, y));
+                }
+            }
+            // built one at a time, so the first refit measures it
+            assert!(!tree.refit(|r, _| r));
+
+            // drift a little every step, the layout still fits
+            for step in 1..4 {
+                assert!(!tree.refit(|r, _| r.translate(Point::new(1, 2))));
+                for x in -20..20 {
+                    for y in -20..20 {
+                        let moved = grid(x, y).translate(Point::new(step, step * 2));
+                        let found: Vec<_> = tree.query(moved).map(|(_, &v)| v).collect();
+                        assert_eq!(found, vec![(x, y)]);
+                    }
+                }
+            }
+
+            // shuffle everything, the leaves no longer fit and it is sorted again
+            assert!(tree.refit(|_, &(x, y)| grid(scatter(x), scatter(y))));
+            for x in -20..20 {
+                for y in -20..20 {
+                    let found: Vec<_> = tree.query(grid(scatter(x), scatter(y))).map(|(_, &v)| v).collect();
+                    assert_eq!(found, vec![(x, y)]);
+                }
+            }
+            assert!(!tree.refit(|r, _| r));
+        }
+    }
+
+    #[test]
+    fn refit_churn() {
+        // values come and go between refits like bullets, the layout
+        // stays as good as it was so the tree is never sorted
+        let mut tree = RTree::new();
+        for x in -20..20 {
+            for y in -20..20 {
+                tree.insert(grid(x, y), (x, y));
+            }
+        }
+        for step in 0..20 {
+            for i in 0..40 {
+                tree.insert(grid(i - 20, step - 20), (100 + step, i));
+                if step > 0 {
+                    tree.remove(grid(i - 20, step - 21), &(99 + step, i));
+                }
+            }
+            let by = if step % 2 == 0 { 1 } else { -1 };
+            assert!(!tree.refit(|r, _| r.translate(Point::new(by, 0))));
+        }
+    }
+
+    #[test]
+    fn resort_threshold() {
+        let mut tree: RTree<(i16, i16)> = (-20..20)
+            .flat_map(|x| (-20..20).map(move |y| (grid(x, y), (x, y))))
+            .collect();
+        tree.set_resort_threshold(std::f64::INFINITY);
+        assert!(!tree.refit(|_, &(x, y)| grid(scatter(x), scatter(y))));
+        assert_eq!(1600, tree.query(grid(0, 0).expand_by(300)).count());
+
+        tree.set_resort_threshold(0.);
+        assert!(tree.refit(|r, _| r));
+    }
+
+    #[test]
+    fn sort_adaptive() {
+        fn sort_adaptive(mut v: Vec<i32>, swaps: Vec<usize>) -> bool {
+            let mut sorted = v.clone();
+            sorted.sort();
+            super::sort_adaptive(&mut v, |a, b| a.cmp(b));
+            let ok = v == sorted;
+
+            // shuffle a sorted list a little
+            for i in swaps {
+                if v.len() > 1 {
+                    let i = i % (v.len() - 1);
+                    v.swap(i, i + 1);
+                }
+            }
+            super::sort_adaptive(&mut v, |a, b| a.cmp(b));
+            ok && v == sorted
+        }
+        quickcheck(sort_adaptive as fn(Vec<i32>, Vec<usize>) -> bool);
+    }
+
+    #[test]
+    fn nearest() {
+        let mut keys = Vec::new();
+        for x in -20..20 {
+            for y in -20..20 {
+                keys.push((grid(x, y), (x, y)));
+            }
+        }
+        let tree: RTree<_> = keys.iter().cloned().collect();
+
+        let dist = |r: &Rectangle, p: Point| {
+            let dx = std::cmp::max(0, std::cmp::max(r.min.x - p.x, p.x - r.max.x)) as i64;
+            let dy = std::cmp::max(0, std::cmp::max(r.min.y - p.y, p.y - r.max.y)) as i64;
+            dx * dx + dy * dy
+        };
+
+        for &(px, py) in &[(0, 0), (7, 7), (-133, 58), (500, -500), (2, 199)] {
+            let p = Point::new(px, py);
+            let found = tree.nearest(p, 10);
+            assert_eq!(10, found.len());
+
+            let mut expected: Vec<_> = keys.iter().map(|&(r, _)| dist(&r, p)).collect();
+            expected.sort();
+            let found: Vec<_> = found.iter().map(|&(r, _)| dist(&r, p)).collect();
+            assert_eq!(&expected[..10], &found[..]);
+        }
+
+        assert_eq!(vec![(grid(2, 3), &(2, 3))], tree.nearest(Point::new(22, 32), 1));
+        assert_eq!(1600, tree.nearest(Point::new(0, 0), 5000).len());
+        assert_eq!(0, RTree::<()>::new().nearest(Point::new(0, 0), 1).len());
+    }
+
+    #[test]
+    fn nearest_within() {
+        let mut keys = Vec::new();
+        for x in -20..20 {
+            for y in -20..20 {
+                keys.push((grid(x, y), (x, y)));
+            }
+        }
+        let tree: RTree<_> = keys.drain(..).collect();
+
+        let p = Point::new(7, 7);
+        assert_eq!(0, tree.nearest_within(p, 10, 1.).len());
+        assert_eq!(1, tree.nearest_within(p, 10, 3.).len());
+        assert_eq!(4, tree.nearest_within(p, 10, 5.).len());
+        assert_eq!(2, tree.nearest_within(p, 2, 5.).len());
+        assert_eq!(vec![(grid(0, 0), &(0, 0))], tree.nearest_within(Point::new(5, 5), 10, 0.));
+    }
+
+    #[test]
+    fn segment() {
+        let mut keys = Vec::new();
+        for x in -20..20 {
+            for y in -20..20 {
+                keys.push((grid(x, y), (x, y)));
+            }
+        }
+        let tree: RTree<_> = keys.drain(..).collect();
+
+        let found: Vec<_> = tree.segment(Point::new(2, 2), Point::new(500, 2))
+            .into_iter()
+            .map(|(_, &v)| v)
+            .collect();
+        let expected: Vec<_> = (0..20).map(|x| (x, 0)).collect();
+        assert_eq!(expected, found);
+
+        let found: Vec<_> = tree.segment(Point::new(2, 2), Point::new(-500, -500))
+            .into_iter()
+            .map(|(_, &v)| v)
+            .collect();
+        let expected: Vec<_> = (0..21).map(|x| (-x, -x)).collect();
+        assert_eq!(expected, found);
+
+        // passes through the gaps between the cells
+        assert_eq!(0, tree.segment(Point::new(-200, 7), Point::new(200, 7)).len());
+        assert_eq!(1, tree.segment(Point::new(5, 5), Point::new(5, 5)).len());
+
+        // (0, -1) is on the segment and (-1, -2) misses it by a fraction
+        // of 2^-63, comparing in f64 would call both hits
+        let dot = |x, y| Rectangle { min: Point::new(x, y), max: Point::new(x, y) };
+        let tree: RTree<u8, i32> = vec![(dot(0, -1), 0), (dot(-1, -2), 1)].into_iter().collect();
+        let (from, to) = (Point::new(-2147483647, -2147483647), Point::new(2147483647, 2147483645));
+        let found: Vec<_> = tree.segment(from, to).into_iter().map(|(_, &v)| v).collect();
+        assert_eq!(vec![0], found);
+    }
+
+    #[test]
+    fn raycast() {
+        let mut keys = Vec::new();
+        for x in -20..20 {
+            for y in -20..20 {
+                keys.push((grid(x, y), (x, y)));
+            }
+        }
+        let tree: RTree<_> = keys.drain(..).collect();
+
+        let found: Vec<_> = tree.raycast(Point::new(2, 2), Point::new(0, 1), 100.)
+            .into_iter()
+            .map(|(_, &v)| v)
+            .collect();
+        let expected: Vec<_> = (0..11).map(|y| (0, y)).collect();
+        assert_eq!(expected, found);
+
+        let found: Vec<_> = tree.raycast(Point::new(2, 2), Point::new(-3, 0), 1000.)
+            .into_iter()
+            .map(|(_, &v)| v)
+            .collect();
+        let expected: Vec<_> = (0..21).map(|x| (-x, 0)).collect();
+        assert_eq!(expected, found);
+
+        let far = Point::new(std::i16::MAX - 1, 2);
+        assert_eq!(40, tree.raycast(far, Point::new(-1, 0), std::f64::INFINITY).len());
+        assert_eq!(0, tree.raycast(far, Point::new(1, 0), std::f64::INFINITY).len());
+        assert_eq!(1, tree.raycast(Point::new(5, 5), Point::new(0, 0), 10.).len());
+
+        // the ray ends at (0.63, 1.9), rounding that to (1, 2) would
+        // reach the point at (1, 2)
+        let dot = |x, y| Rectangle { min: Point::new(x, y), max: Point::new(x, y) };
+        let tree: RTree<_> = vec![(dot(1, 2), 0), (dot(2, 6), 1), (dot(1, 4), 2)].into_iter().collect();
+        assert_eq!(0, tree.raycast(Point::new(0, 0), Point::new(1, 3), 2.).len());
+        let found: Vec<_> = tree.raycast(Point::new(0, 0), Point::new(1, 3), 100.)
+            .into_iter()
+            .map(|(_, &v)| v)
+            .collect();
+        assert_eq!(vec![1], found);
+    }
+
+    #[test]
+    fn intersecting_pairs() {
+        let mut keys = Vec::new();
+        for x in -15..15 {
+            for y in -15..15 {
+                // each cell reaches the edge of its neighbours and overlaps
+                // the cell two to the right
                 let rect = Rectangle {
                     min: Point::new(x * 10, y * 10),
-                    max: Point::new(x * 10 + 5, y * 10 + 5),
+                    max: Point::new(x * 10 + if x % 3 == 0 { 25 } else { 10 }, y * 10 + 5),
                 };
                 keys.push((rect, (x, y)));
             }
         }
 
-        let tree: RTree<_> = keys.drain(..).collect();
+        let mut tree: RTree<_> = keys.iter().cloned().collect();
+        tree.insert(keys[0].0, (100, 100));
 
-        assert_eq!(400,
-                   tree.query(Rectangle {
-                           max: Point { x: 100, y: 100 },
-                           min: Point { x: -100, y: -100 },
-                       })
-                       .count());
+        for &touch in &[Touch::Overlaps, Touch::Intersects] {
+            let mut expected = vec![];
+            for (i, a) in keys.iter().enumerate() {
+                for b in &keys[i + 1..] {
+                    if touch.test(a.0, b.0) {
+                        expected.push(if a.1 < b.1 { (a.1, b.1) } else { (b.1, a.1) });
+                    }
+                }
+                if touch.test(a.0, keys[0].0) {
+                    expected.push((a.1, (100, 100)));
+                }
+            }
 
-        for x in -10..10 {
-            for y in -10..10 {
-                assert_eq!(1,
-                           tree.query(Rectangle {
-                                   min: Point::new(x * 10, y * 10),
-                                   max: Point::new(x * 10 + 5, y * 10 + 5),
-                               })
-                               .count());
+            let mut found: Vec<_> = tree.intersecting_pairs(touch)
+                .into_iter()
+                .map(|((_, &a), (_, &b))| if a < b { (a, b) } else { (b, a) })
+                .collect();
+
+            expected.sort();
+            found.sort();
+            assert_eq!(expected, found);
+        }
+    }
+
+    #[test]
+    fn strategies() {
+        let mut keys = Vec::new();
+        for x in -20..20 {
+            for y in -20..20 {
+                // long thin rectangles that span several cells
+                let rect = Rectangle {
+                    min: Point::new(x * 10, y * 10),
+                    max: Point::new(x * 10 + if y % 2 == 0 { 45 } else { 5 }, y * 10 + 5),
+                };
+                keys.push((rect, (x, y)));
             }
         }
 
-        for x in -5..5 {
-            for y in -5..5 {
-                assert_eq!(4,
-                           tree.query(Rectangle {
-                                   min: Point::new(x * 20, y * 20),
-                                   max: Point::new(x * 20 + 15, y * 20 + 15),
-                               })
-                               .count());
+        let query = |x: i16, y: i16| Rectangle {
+            min: Point::new(x * 7, y * 7),
+            max: Point::new(x * 7 + 12, y * 7 + 12),
+        };
+
+        let morton: RTree<_> = keys.iter().cloned().collect();
+        for &strategy in &[Strategy::Hilbert, Strategy::SortTileRecursive] {
+            let mut tree = RTree::with_strategy(strategy);
+            tree.extend(keys.iter().cloned());
+
+            for x in -30..30 {
+                for y in -30..30 {
+                    let mut a: Vec<_> = morton.query(query(x, y)).map(|(_, &v)| v).collect();
+                    let mut b: Vec<_> = tree.query(query(x, y)).map(|(_, &v)| v).collect();
+                    a.sort();
+                    b.sort();
+                    assert_eq!(a, b);
+                }
             }
+
+            let quality = tree.quality();
+            assert!(quality.area > 0.);
+            assert!(quality.dead_space >= 0.);
+            assert!(quality.overlap >= 0.);
         }
 
-        for x in -2..2 {
-            for y in -2..2 {
-                assert_eq!(25,
-                           tree.query(Rectangle {
-                                   min: Point::new(x * 50, y * 50),
-                                   max: Point::new(x * 50 + 45, y * 50 + 45),
-                               })
-                               .count());
+        // hilbert and str should both pack a grid much tighter
+        let mut keys = Vec::new();
+        for x in 0..64 {
+            for y in 0..64 {
+                let rect = Rectangle {
+                    min: Point::new(x * 10, y * 10),
+                    max: Point::new(x * 10 + 10, y * 10 + 10),
+                };
+                keys.push((rect, (x, y)));
             }
         }
+        let mut tree: RTree<_> = keys.iter().cloned().collect();
+        let morton = tree.quality();
+        for &strategy in &[Strategy::Hilbert, Strategy::SortTileRecursive] {
+            tree.set_strategy(strategy);
+            let quality = tree.quality();
+            assert!(quality.area < morton.area);
+            assert!(quality.overlap < morton.overlap);
+            assert!(quality.dead_space < morton.dead_space);
+            assert_eq!(4096, tree.query(query(-1000, -1000).extend(query(1000, 1000))).count());
+        }
 
-        for x in -1..1 {
-            for y in -1..1 {
-                assert_eq!(100,
-                           tree.query(Rectangle {
-                                   min: Point::new(x * 100, y * 100),
-                                   max: Point::new(x * 100 + 95, y * 100 + 95),
-                               })
-                               .count());
+        assert_eq!(Quality::default(), RTree::<()>::new().quality());
+    }
+
+    #[test]
+    fn wide_coordinates() {
+        // a grid far outside of what i16 can hold
+        let mut tree = RTree::new();
+        for x in -20..20 {
+            for y in -20..20 {
+                let rect = Rectangle {
+                    min: Point::new(x * 100_000, y * 100_000),
+                    max: Point::new(x * 100_000 + 50_000, y * 100_000 + 50_000),
+                };
+                tree.insert(rect, (x, y));
             }
         }
+
+        let query = Rectangle {
+            min: Point::new(-149_000, -149_000),
+            max: Point::new(120_000, 120_000),
+        };
+        assert_eq!(9, tree.query(query).count());
+        assert_eq!(&(3, 4), tree.nearest(Point::new(340_000, 420_000), 1)[0].1);
+        assert_eq!(40, tree.raycast(Point::new(i32::min_value(), 10), Point::new(1, 0), 1e12).len());
     }
 
     #[test]
-    fn insert_close() {
+    fn float_coordinates() {
         let mut keys = Vec::new();
-
-        for x in -100..100 {
-            for y in -100..100 {
+        for x in -20..20 {
+            for y in -20..20 {
+                let (x, y) = (x as f32 * 0.1, y as f32 * 0.1);
                 let rect = Rectangle {
                     min: Point::new(x, y),
-                    max: Point::new(x, y),
+                    max: Point::new(x + 0.05, y + 0.05),
                 };
                 keys.push((rect, (x, y)));
             }
         }
 
-        let tree: RTree<_> = keys.drain(..).collect();
+        for &strategy in &[Strategy::Morton, Strategy::Hilbert, Strategy::SortTileRecursive] {
+            let mut tree = RTree::with_strategy(strategy);
+            tree.extend(keys.iter().cloned());
 
-        let all = Rectangle {
-            min: Point::new(-100, -100),
-            max: Point::new(100, 100),
-        };
+            let query = Rectangle {
+                min: Point::new(-0.01, -0.01),
+                max: Point::new(0.26, 0.26),
+            };
+            assert_eq!(9, tree.query(query).count());
+            assert_eq!(4, tree.nearest_within(Point::new(0.075, 0.075), 10, 0.04).len());
+            assert_eq!(20, tree.segment(Point::new(0.01, 0.01), Point::new(10., 0.01)).len());
+            assert_eq!(2, tree.raycast(Point::new(0.01, 0.01), Point::new(1., 1.), 0.2).len());
+        }
+    }
 
-        assert_eq!(tree.query(all.into()).count(), 40_000);
+    #[test]
+    fn translate() {
+        fn translate(ax: i16, ay: i16, bx: i16, by: i16, x: i16, y: i16) -> bool {
+            let r = rect(ax, ay, bx, by);
+            let moved = r.translate(Point::new(x, y));
+            moved.min.x == clamp(r.min.x as i32 + x as i32) &&
+            moved.min.y == clamp(r.min.y as i32 + y as i32) &&
+            moved.max.x == clamp(r.max.x as i32 + x as i32) &&
+            moved.max.y == clamp(r.max.y as i32 + y as i32)
+        }
+        quickcheck(translate as fn(i16, i16, i16, i16, i16, i16) -> bool);
+
+        let r = rect(-1, -1, 1, 1);
+        assert_eq!(rect(4, -6, 6, -4), r.translate(Point::new(5, -5)));
+        assert_eq!(rect(i16::max_value() - 1, 0, i16::max_value(), 2),
+                   r.translate(Point::new(i16::max_value(), 1)));
+    }
+
+    #[test]
+    fn from_center_size() {
+        fn from_center_size(x: i16, y: i16, w: u8, h: u8) -> bool {
+            let center = Point::new(x, y);
+            let r = Rectangle::from_center_size(center, w as i16, h as i16);
+            let fits = |c: i16, s: u8| {
+                (c as i32 - s as i32 / 2) >= i16::min_value() as i32 &&
+                (c as i32 - s as i32 / 2 + s as i32) <= i16::max_value() as i32
+            };
+            r.contains_point(center) &&
+            (!fits(x, w) || r.width() == w as i16) &&
+            (!fits(y, h) || r.height() == h as i16) &&
+            (!fits(x, w) || !fits(y, h) || r.center() == center || w % 2 == 1 || h % 2 == 1)
+        }
+        quickcheck(from_center_size as fn(i16, i16, u8, u8) -> bool);
+
+        assert_eq!(rect(-2, -1, 3, 1), Rectangle::from_center_size(Point::new(0, 0), 5, 2));
+        assert_eq!(rect(i16::min_value(), -1, i16::min_value() + 8, 1),
+                   Rectangle::from_center_size(Point::new(i16::min_value(), 0), 8, 2));
+    }
+
+    #[test]
+    fn size() {
+        fn size(ax: i16, ay: i16, bx: i16, by: i16) -> bool {
+            let r = rect(ax, ay, bx, by);
+            let (w, h) = (r.max.x as i32 - r.min.x as i32, r.max.y as i32 - r.min.y as i32);
+            r.width() == clamp(w) && r.height() == clamp(h) && r.area() == (w as f64) * (h as f64)
+        }
+        quickcheck(size as fn(i16, i16, i16, i16) -> bool);
+
+        let all = rect(i16::min_value(), i16::min_value(), i16::max_value(), i16::max_value());
+        assert_eq!(i16::max_value(), all.width());
+        assert_eq!(65535. * 65535., all.area());
+        assert_eq!(0, Rectangle::<i16>::empty().width());
+        assert_eq!(0., Rectangle::<i16>::empty().area());
+    }
+
+    #[test]
+    fn center() {
+        fn center(ax: i16, ay: i16, bx: i16, by: i16) -> bool {
+            let r = rect(ax, ay, bx, by);
+            let c = r.center();
+            let mid = |a: i16, b: i16, c: i16| ((a as i32 + b as i32) - 2 * c as i32).abs() <= 1;
+            r.contains_point(c) && mid(r.min.x, r.max.x, c.x) && mid(r.min.y, r.max.y, c.y)
+        }
+        quickcheck(center as fn(i16, i16, i16, i16) -> bool);
+
+        assert_eq!(Point::new(0, 2), rect(-4, 0, 4, 4).center());
+        let all = rect(i16::max_value() - 1, 0, i16::max_value(), 0);
+        assert_eq!(Point::new(i16::max_value(), 0), all.center());
+    }
+
+    #[test]
+    fn contains() {
+        fn contains(ax: i16, ay: i16, bx: i16, by: i16, x: i16, y: i16) -> bool {
+            let r = rect(ax, ay, bx, by);
+            let p = Point::new(x, y);
+            let inside = r.min.x <= x && x <= r.max.x && r.min.y <= y && y <= r.max.y;
+            r.contains_point(p) == inside &&
+            r.contains_point(p) == r.intersects(Rectangle { min: p, max: p }) &&
+            r.contains_rect(r) &&
+            r.contains_rect(Rectangle { min: p, max: p }) == inside
+        }
+        quickcheck(contains as fn(i16, i16, i16, i16, i16, i16) -> bool);
+
+        let r = rect(0, 0, 10, 10);
+        assert!(r.contains_rect(rect(0, 0, 10, 5)));
+        assert!(!r.contains_rect(rect(-1, 0, 10, 5)));
+        assert!(!rect(0, 0, 10, 5).contains_rect(r));
+    }
+
+    #[test]
+    fn intersection() {
+        fn intersection(a: (i16, i16, i16, i16), b: (i16, i16, i16, i16)) -> bool {
+            let a = rect(a.0, a.1, a.2, a.3);
+            let b = rect(b.0, b.1, b.2, b.3);
+            match a.intersection(b) {
+                Some(i) => a.intersects(b) && a.contains_rect(i) && b.contains_rect(i),
+                None => !a.intersects(b),
+            }
+        }
+        quickcheck(intersection as fn((i16, i16, i16, i16), (i16, i16, i16, i16)) -> bool);
+
+        assert_eq!(Some(rect(5, 5, 10, 8)), rect(0, 0, 10, 8).intersection(rect(5, 5, 20, 20)));
+        assert_eq!(Some(rect(10, 0, 10, 8)), rect(0, 0, 10, 8).intersection(rect(10, 0, 20, 20)));
+        assert_eq!(None, rect(0, 0, 10, 8).intersection(rect(11, 0, 20, 20)));
+    }
+
+    #[test]
+    fn expand_by() {
+        fn expand_by(ax: i16, ay: i16, bx: i16, by: i16, n: u8) -> bool {
+            let r = rect(ax, ay, bx, by);
+            let n = n as i16;
+            let grown = r.expand_by(n);
+            grown.contains_rect(r) &&
+            grown.min.x == clamp(r.min.x as i32 - n as i32) &&
+            grown.max.y == clamp(r.max.y as i32 + n as i32) &&
+            (grown.min.x == i16::min_value() || grown.min.y == i16::min_value() ||
+             grown.max.x == i16::max_value() || grown.max.y == i16::max_value() ||
+             grown.expand_by(-n) == r)
+        }
+        quickcheck(expand_by as fn(i16, i16, i16, i16, u8) -> bool);
+
+        assert_eq!(rect(-3, -3, 3, 3), rect(-1, -1, 1, 1).expand_by(2));
+        assert_eq!(rect(0, 0, 0, 0), rect(-1, -1, 1, 1).expand_by(-1));
+    }
+
+    #[test]
+    fn distance_to_point() {
+        fn distance_to_point(ax: i16, ay: i16, bx: i16, by: i16, x: i16, y: i16) -> bool {
+            let r = rect(ax, ay, bx, by);
+            let p = Point::new(x, y);
+            let d = r.distance_to_point(p);
+            let dx = (r.min.x as i32 - x as i32).max(x as i32 - r.max.x as i32).max(0) as f64;
+            let dy = (r.min.y as i32 - y as i32).max(y as i32 - r.max.y as i32).max(0) as f64;
+            (d == 0.) == r.contains_point(p) && d == (dx * dx + dy * dy).sqrt()
+        }
+        quickcheck(distance_to_point as fn(i16, i16, i16, i16, i16, i16) -> bool);
+
+        let r = rect(0, 0, 10, 10);
+        assert_eq!(0., r.distance_to_point(Point::new(5, 10)));
+        assert_eq!(5., r.distance_to_point(Point::new(13, 14)));
+        assert_eq!(65535., rect(i16::min_value(), 0, i16::min_value(), 0)
+                              .distance_to_point(Point::new(i16::max_value(), 0)));
     }
 }
\ No newline at end of file
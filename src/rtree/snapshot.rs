@@ -0,0 +1,682 @@
+//! Saving an `RTree` to bytes and loading it back.
+//!
+//! Everything is little endian. The file is laid out as a header, a
+//! table of fixed size node records, a table of fixed size value slot
+//! records, the free lists, the encoded values and finally a checksum
+//! of everything before it. Because the tables are fixed size a
+//! `Snapshot` can query the bytes directly, only the values that are
+//! found get decoded.
+
+use std;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+
+use super::{RTree, Node, Rectangle, Point, Coordinate, PointsAt, Strategy, FANOUT_SIZE};
+
+const MAGIC: &'static [u8; 4] = b"RTRE";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 4 + 4 + 4 + 1 + 8 + 8 + 8 * 4;
+const CHECKSUM_SIZE: usize = 8;
+/// the payload offset of a slot that holds no value
+const EMPTY: u64 = std::u64::MAX;
+
+/// a value that can be stored in a snapshot
+pub trait Codec: Sized {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()>;
+    fn decode<R: Read>(r: &mut R) -> io::Result<Self>;
+}
+
+macro_rules! codec_int {
+    ($($t:ty => $u:ty),*) => {$(
+        impl Codec for $t {
+            fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+                let mut buf = [0; 8];
+                put_uint(&mut buf, *self as $u as u64);
+                w.write_all(&buf[..std::mem::size_of::<$t>()])
+            }
+
+            fn decode<R: Read>(r: &mut R) -> io::Result<$t> {
+                let mut buf = [0; 8];
+                r.read_exact(&mut buf[..std::mem::size_of::<$t>()])?;
+                Ok(get_uint(&buf) as $u as $t)
+            }
+        }
+    )*}
+}
+
+codec_int!(u8 => u8, u16 => u16, u32 => u32, u64 => u64,
+           i8 => u8, i16 => u16, i32 => u32, i64 => u64);
+
+impl Codec for f32 {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.to_bits().encode(w)
+    }
+
+    fn decode<R: Read>(r: &mut R) -> io::Result<f32> {
+        u32::decode(r).map(f32::from_bits)
+    }
+}
+
+impl Codec for f64 {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.to_bits().encode(w)
+    }
+
+    fn decode<R: Read>(r: &mut R) -> io::Result<f64> {
+        u64::decode(r).map(f64::from_bits)
+    }
+}
+
+impl Codec for bool {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        (*self as u8).encode(w)
+    }
+
+    fn decode<R: Read>(r: &mut R) -> io::Result<bool> {
+        match u8::decode(r)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid("bad bool")),
+        }
+    }
+}
+
+impl Codec for () {
+    fn encode<W: Write>(&self, _: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn decode<R: Read>(_: &mut R) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<A: Codec, B: Codec> Codec for (A, B) {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.0.encode(w)?;
+        self.1.encode(w)
+    }
+
+    fn decode<R: Read>(r: &mut R) -> io::Result<(A, B)> {
+        Ok((A::decode(r)?, B::decode(r)?))
+    }
+}
+
+impl<A: Codec> Codec for Vec<A> {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        (self.len() as u64).encode(w)?;
+        for a in self {
+            a.encode(w)?;
+        }
+        Ok(())
+    }
+
+    fn decode<R: Read>(r: &mut R) -> io::Result<Vec<A>> {
+        let len = u64::decode(r)?;
+        // don't trust the length enough to reserve it up front
+        let mut out = vec![];
+        for _ in 0..len {
+            out.push(A::decode(r)?);
+        }
+        Ok(out)
+    }
+}
+
+impl Codec for String {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        (self.len() as u64).encode(w)?;
+        w.write_all(self.as_bytes())
+    }
+
+    fn decode<R: Read>(r: &mut R) -> io::Result<String> {
+        let len = u64::decode(r)?;
+        let mut bytes = vec![];
+        r.take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err(invalid("string is cut short"));
+        }
+        String::from_utf8(bytes).map_err(|_| invalid("string is not utf-8"))
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// write `v` into `buf` little endian, as many bytes as fit
+fn put_uint(buf: &mut [u8], v: u64) {
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b = (v >> (i * 8)) as u8;
+    }
+}
+
+/// read a little endian integer that fills `buf`
+fn get_uint(buf: &[u8]) -> u64 {
+    buf.iter().rev().fold(0, |acc, &b| acc << 8 | b as u64)
+}
+
+/// 64 bit FNV-1a
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn push_uint(out: &mut Vec<u8>, v: u64, size: usize) {
+    let mut buf = [0; 8];
+    put_uint(&mut buf, v);
+    out.extend_from_slice(&buf[..size]);
+}
+
+/// the bytes used by one coordinate
+fn coord_size<C: Coordinate>() -> usize {
+    C::bits() as usize / 8
+}
+
+/// the bits of the largest coordinate, this tells apart coordinate
+/// types that are the same size
+fn coord_tag<C: Coordinate>() -> u32 {
+    C::max_value().to_bits()
+}
+
+fn rect_size<C: Coordinate>() -> usize {
+    coord_size::<C>() * 4
+}
+
+fn node_size<C: Coordinate>() -> usize {
+    rect_size::<C>() + 1 + 8 * 3
+}
+
+fn slot_size<C: Coordinate>() -> usize {
+    rect_size::<C>() + 8
+}
+
+fn push_rect<C: Coordinate>(out: &mut Vec<u8>, r: Rectangle<C>) {
+    for &c in &[r.min.x, r.min.y, r.max.x, r.max.y] {
+        push_uint(out, c.to_bits() as u64, coord_size::<C>());
+    }
+}
+
+fn get_rect<C: Coordinate>(buf: &[u8]) -> Rectangle<C> {
+    let size = coord_size::<C>();
+    let c = |i: usize| C::from_bits(get_uint(&buf[i * size..(i + 1) * size]) as u32);
+    Rectangle {
+        min: Point::new(c(0), c(1)),
+        max: Point::new(c(2), c(3)),
+    }
+}
+
+fn strategy_tag(strategy: Strategy) -> u8 {
+    match strategy {
+        Strategy::Morton => 0,
+        Strategy::Hilbert => 1,
+        Strategy::SortTileRecursive => 2,
+    }
+}
+
+impl<T: Codec, C: Coordinate> RTree<T, C> {
+    /// save the tree, `read_from` or a `Snapshot` can load it back
+    /// as long as they use the same coordinate type
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut payload = vec![];
+        let mut offsets = Vec::with_capacity(self.values.len());
+        for v in &self.values {
+            match *v {
+                Some((_, ref value)) => {
+                    offsets.push(payload.len() as u64);
+                    value.encode(&mut payload)?;
+                }
+                None => offsets.push(EMPTY),
+            }
+        }
+
+        let mut out = Vec::with_capacity(HEADER_SIZE + payload.len());
+        out.extend_from_slice(MAGIC);
+        push_uint(&mut out, VERSION as u64, 4);
+        push_uint(&mut out, coord_tag::<C>() as u64, 4);
+        out.push(strategy_tag(self.strategy));
+        push_uint(&mut out, self.resort_threshold.to_bits(), 8);
+        push_uint(&mut out, self.sorted_area.unwrap_or(std::f64::NAN).to_bits(), 8);
+        for &len in &[self.nodes.len(), self.values.len(),
+                      self.free_nodes.len(), self.free_values.len()] {
+            push_uint(&mut out, len as u64, 8);
+        }
+
+        for node in &self.nodes {
+            push_rect(&mut out, node.rect);
+            out.push(match node.leaf {
+                PointsAt::Leaf => 0,
+                PointsAt::Inner => 1,
+            });
+            for &v in &[node.start, node.stop, node.count] {
+                push_uint(&mut out, v as u64, 8);
+            }
+        }
+        for (v, &offset) in self.values.iter().zip(offsets.iter()) {
+            push_rect(&mut out, v.as_ref().map_or(Rectangle::empty(), |v| v.0));
+            push_uint(&mut out, offset, 8);
+        }
+        for &free in self.free_nodes.iter().chain(self.free_values.iter()) {
+            push_uint(&mut out, free as u64, 8);
+        }
+        out.extend_from_slice(&payload);
+
+        let sum = checksum(&out);
+        push_uint(&mut out, sum, 8);
+        w.write_all(&out)
+    }
+
+    /// load a tree saved with `write_to`
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<RTree<T, C>> {
+        let mut bytes = vec![];
+        r.read_to_end(&mut bytes)?;
+        Snapshot::new(&bytes)?.to_tree()
+    }
+}
+
+/// a saved tree that is queried straight from its bytes, for example
+/// from a memory mapped file. Only the values a query finds are decoded
+pub struct Snapshot<'a, T, C> {
+    bytes: &'a [u8],
+    strategy: Strategy,
+    resort_threshold: f64,
+    sorted_area: f64,
+    nodes: usize,
+    values: usize,
+    free_nodes: usize,
+    free_values: usize,
+    phantom: PhantomData<(T, C)>,
+}
+
+impl<'a, T: Codec, C: Coordinate> Snapshot<'a, T, C> {
+    /// check the header and checksum of a saved tree
+    pub fn new(bytes: &'a [u8]) -> io::Result<Snapshot<'a, T, C>> {
+        if bytes.len() < HEADER_SIZE + CHECKSUM_SIZE || &bytes[..4] != MAGIC {
+            return Err(invalid("not an rtree snapshot"));
+        }
+        if get_uint(&bytes[4..8]) != VERSION as u64 {
+            return Err(invalid("unsupported rtree snapshot version"));
+        }
+        if get_uint(&bytes[8..12]) != coord_tag::<C>() as u64 {
+            return Err(invalid("rtree snapshot has a different coordinate type"));
+        }
+
+        let (body, sum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+        if checksum(body) != get_uint(sum) {
+            return Err(invalid("rtree snapshot checksum does not match"));
+        }
+
+        let strategy = match bytes[12] {
+            0 => Strategy::Morton,
+            1 => Strategy::Hilbert,
+            2 => Strategy::SortTileRecursive,
+            _ => return Err(invalid("unknown rtree strategy")),
+        };
+        let len = |i: usize| get_uint(&bytes[29 + i * 8..37 + i * 8]) as usize;
+        let snapshot = Snapshot {
+            bytes: body,
+            strategy: strategy,
+            resort_threshold: f64::from_bits(get_uint(&bytes[13..21])),
+            sorted_area: f64::from_bits(get_uint(&bytes[21..29])),
+            nodes: len(0),
+            values: len(1),
+            free_nodes: len(2),
+            free_values: len(3),
+            phantom: PhantomData,
+        };
+
+        if snapshot.payload_start().map_or(true, |start| start > body.len()) {
+            return Err(invalid("rtree snapshot is cut short"));
+        }
+        for i in 0..snapshot.nodes {
+            let node = snapshot.node(i);
+            let limit = match node.leaf {
+                PointsAt::Leaf => snapshot.values,
+                PointsAt::Inner => snapshot.nodes,
+            };
+            if node.start > node.stop || node.stop > limit {
+                return Err(invalid("rtree snapshot node is out of range"));
+            }
+        }
+
+        // every node below the root can only be reached once, otherwise a
+        // node could list itself or an ancestor and a query never end
+        let mut seen = vec![false; snapshot.nodes];
+        let mut to_check: Vec<usize> = snapshot.nodes.checked_sub(1).into_iter().collect();
+        while let Some(idx) = to_check.pop() {
+            if seen[idx] {
+                return Err(invalid("rtree snapshot nodes do not form a tree"));
+            }
+            seen[idx] = true;
+            let node = snapshot.node(idx);
+            if node.leaf == PointsAt::Inner {
+                to_check.extend(node.start..node.stop);
+            }
+        }
+        Ok(snapshot)
+    }
+
+    fn nodes_start(&self) -> usize {
+        HEADER_SIZE
+    }
+
+    fn slots_start(&self) -> usize {
+        self.nodes_start() + self.nodes * node_size::<C>()
+    }
+
+    fn free_start(&self) -> usize {
+        self.slots_start() + self.values * slot_size::<C>()
+    }
+
+    /// where the encoded values start, none if the counts in the
+    /// header are too large to be real
+    fn payload_start(&self) -> Option<usize> {
+        let free = self.free_nodes.checked_add(self.free_values)
+            .and_then(|n| n.checked_mul(8));
+        self.nodes.checked_mul(node_size::<C>())
+            .and_then(|n| self.values.checked_mul(slot_size::<C>()).and_then(|v| n.checked_add(v)))
+            .and_then(|n| free.and_then(|f| n.checked_add(f)))
+            .and_then(|n| n.checked_add(HEADER_SIZE))
+    }
+
+    fn node(&self, idx: usize) -> Node<C> {
+        let start = self.nodes_start() + idx * node_size::<C>();
+        let buf = &self.bytes[start..start + node_size::<C>()];
+        let rest = &buf[rect_size::<C>()..];
+        Node {
+            rect: get_rect(buf),
+            leaf: if rest[0] == 0 { PointsAt::Leaf } else { PointsAt::Inner },
+            start: get_uint(&rest[1..9]) as usize,
+            stop: get_uint(&rest[9..17]) as usize,
+            count: get_uint(&rest[17..25]) as usize,
+        }
+    }
+
+    /// the rectangle in a slot and where its value is in the payload
+    fn slot(&self, idx: usize) -> (Rectangle<C>, u64) {
+        let start = self.slots_start() + idx * slot_size::<C>();
+        let buf = &self.bytes[start..start + slot_size::<C>()];
+        (get_rect(buf), get_uint(&buf[rect_size::<C>()..]))
+    }
+
+    fn value(&self, offset: u64) -> io::Result<T> {
+        let start = self.payload_start().unwrap() as u64;
+        match start.checked_add(offset) {
+            Some(at) if at <= self.bytes.len() as u64 => T::decode(&mut &self.bytes[at as usize..]),
+            _ => Err(invalid("rtree snapshot value is out of range")),
+        }
+    }
+
+    /// the number of values in the tree
+    pub fn len(&self) -> usize {
+        match self.nodes {
+            0 => 0,
+            n => self.node(n - 1).count,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// find every value that intersects `rect`
+    pub fn query(&self, rect: Rectangle<C>) -> io::Result<Vec<(Rectangle<C>, T)>> {
+        let mut found = vec![];
+        let mut to_check: Vec<usize> = self.nodes.checked_sub(1).into_iter().collect();
+        while let Some(idx) = to_check.pop() {
+            let node = self.node(idx);
+            if !node.rect.intersects(rect) {
+                continue;
+            }
+            for i in node.start..node.stop {
+                match node.leaf {
+                    PointsAt::Leaf => {
+                        let (r, offset) = self.slot(i);
+                        if offset == EMPTY {
+                            return Err(invalid("rtree snapshot leaf points at an empty slot"));
+                        }
+                        if r.intersects(rect) {
+                            found.push((r, self.value(offset)?));
+                        }
+                    }
+                    PointsAt::Inner => to_check.push(i),
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// decode everything into a tree that can be changed
+    pub fn to_tree(&self) -> io::Result<RTree<T, C>> {
+        let mut tree = RTree::with_strategy(self.strategy);
+        tree.resort_threshold = self.resort_threshold;
+        tree.sorted_area = if self.sorted_area.is_nan() { None } else { Some(self.sorted_area) };
+        tree.nodes = (0..self.nodes).map(|i| self.node(i)).collect();
+        for i in 0..self.values {
+            let (rect, offset) = self.slot(i);
+            tree.values.push(match offset {
+                EMPTY => None,
+                offset => Some((rect, self.value(offset)?)),
+            });
+        }
+
+        let free = |i: usize| {
+            let start = self.free_start() + i * 8;
+            get_uint(&self.bytes[start..start + 8]) as usize
+        };
+        tree.free_nodes = (0..self.free_nodes).map(&free).collect();
+        tree.free_values = (self.free_nodes..self.free_nodes + self.free_values).map(&free).collect();
+
+        let fits = |blocks: &[usize], len: usize| {
+            blocks.iter().all(|&start| start.checked_add(FANOUT_SIZE).map_or(false, |end| end <= len))
+        };
+        if !fits(&tree.free_nodes, tree.nodes.len()) || !fits(&tree.free_values, tree.values.len()) {
+            return Err(invalid("rtree snapshot free list is out of range"));
+        }
+
+        // the checksum only catches accidents, so make sure the nodes and
+        // slots really do make a tree before anything relies on it
+        if let Err(err) = tree.validate() {
+            return Err(invalid(&format!("rtree snapshot is not a valid tree: {}", err)));
+        }
+        Ok(tree)
+    }
+}
+
+/// a snapshot file mapped into memory
+#[cfg(feature = "mmap")]
+pub struct MappedFile(::memmap::Mmap);
+
+#[cfg(feature = "mmap")]
+impl MappedFile {
+    /// map a file written by `RTree::write_to`. The file must not be
+    /// changed while it is mapped
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> io::Result<MappedFile> {
+        ::memmap::Mmap::open_path(path, ::memmap::Protection::Read).map(MappedFile)
+    }
+
+    pub fn snapshot<T: Codec, C: Coordinate>(&self) -> io::Result<Snapshot<T, C>> {
+        // the map is read only and lives as long as the snapshot borrows it
+        Snapshot::new(unsafe { self.0.as_slice() })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use quickcheck::{QuickCheck, Testable};
+    use super::{Codec, Snapshot};
+    use super::super::{RTree, Rectangle, Point, Strategy};
+
+    fn quickcheck<A: Testable>(a: A) {
+        QuickCheck::new().tests(100).quickcheck(a)
+    }
+
+    fn round_trip<A: Codec + PartialEq>(a: A) -> bool {
+        let mut bytes = vec![];
+        a.encode(&mut bytes).unwrap();
+        A::decode(&mut &bytes[..]).ok() == Some(a)
+    }
+
+    fn grid(x: i16, y: i16) -> Rectangle {
+        Rectangle {
+            min: Point::new(x * 10, y * 10),
+            max: Point::new(x * 10 + 5, y * 10 + 5),
+        }
+    }
+
+    fn sorted<T: Ord, C: Ord>(mut v: Vec<(Rectangle<C>, T)>) -> Vec<(Rectangle<C>, T)> {
+        v.sort();
+        v
+    }
+
+    #[test]
+    fn codec() {
+        quickcheck(round_trip::<(u8, i8)> as fn((u8, i8)) -> bool);
+        quickcheck(round_trip::<(u16, i16)> as fn((u16, i16)) -> bool);
+        quickcheck(round_trip::<(u32, i32)> as fn((u32, i32)) -> bool);
+        quickcheck(round_trip::<(u64, i64)> as fn((u64, i64)) -> bool);
+        quickcheck(round_trip::<Vec<(bool, String)>> as fn(Vec<(bool, String)>) -> bool);
+        assert!(round_trip((1.5f32, -0.25f64)));
+
+        // stored little endian whatever the machine is
+        let mut bytes = vec![];
+        0x0102_0304u32.encode(&mut bytes).unwrap();
+        (-2i16).encode(&mut bytes).unwrap();
+        assert_eq!(vec![4, 3, 2, 1, 0xfe, 0xff], bytes);
+        assert!(u32::decode(&mut &bytes[..3]).is_err());
+    }
+
+    #[test]
+    fn write_read() {
+        let mut tree = RTree::with_strategy(Strategy::Hilbert);
+        tree.extend((-20..20).flat_map(|x| (-20..20).map(move |y| (grid(x, y), (x as u32, y)))));
+        for x in -20..-5 {
+            tree.remove(grid(x, x), &(x as u32, x));
+        }
+        tree.insert(grid(100, 100), (7, 7));
+
+        let mut bytes = vec![];
+        tree.write_to(&mut bytes).unwrap();
+        let read: RTree<(u32, i16)> = RTree::read_from(&mut &bytes[..]).unwrap();
+        let snapshot: Snapshot<(u32, i16), i16> = Snapshot::new(&bytes).unwrap();
+
+        // the layout is kept exactly
+        assert_eq!(tree.nodes, read.nodes);
+        assert_eq!(tree.values, read.values);
+        assert_eq!(tree.free_nodes, read.free_nodes);
+        assert_eq!(tree.free_values, read.free_values);
+        assert_eq!(tree.strategy, read.strategy);
+        assert_eq!(tree.len(), snapshot.len());
+
+        for x in -22..22 {
+            let query = grid(x, -x).expand_by(25);
+            let expected = sorted(tree.query(query).map(|(&r, &v)| (r, v)).collect());
+            assert_eq!(expected, sorted(read.query(query).map(|(&r, &v)| (r, v)).collect()));
+            assert_eq!(expected, sorted(snapshot.query(query).unwrap()));
+        }
+
+        let mut again = vec![];
+        read.write_to(&mut again).unwrap();
+        assert_eq!(bytes, again);
+    }
+
+    #[test]
+    fn float_coordinates() {
+        let mut tree = RTree::new();
+        for i in 0..100 {
+            let f = i as f32 * 0.5;
+            let rect = Rectangle {
+                min: Point::new(f, -f),
+                max: Point::new(f + 0.25, -f + 0.25),
+            };
+            tree.insert(rect, format!("{}", i));
+        }
+
+        let mut bytes = vec![];
+        tree.write_to(&mut bytes).unwrap();
+        let snapshot: Snapshot<String, f32> = Snapshot::new(&bytes).unwrap();
+        let query = Rectangle {
+            min: Point::new(10., -20.),
+            max: Point::new(20., -10.),
+        };
+        let mut found: Vec<String> = snapshot.query(query).unwrap().into_iter().map(|v| v.1).collect();
+        let mut expected: Vec<String> = (20..41).map(|i| format!("{}", i)).collect();
+        found.sort();
+        expected.sort();
+        assert_eq!(expected, found);
+        assert_eq!(100, snapshot.len());
+
+        // the same number of bits but a different coordinate
+        assert!(Snapshot::<String, i32>::new(&bytes).is_err());
+        assert!(Snapshot::<String, i16>::new(&bytes).is_err());
+    }
+
+    #[test]
+    fn empty() {
+        let mut bytes = vec![];
+        RTree::<u8>::new().write_to(&mut bytes).unwrap();
+        let read = RTree::<u8>::read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(0, read.len());
+        assert!(Snapshot::<u8, i16>::new(&bytes).unwrap().query(grid(0, 0)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn corrupt() {
+        let tree: RTree<u16> = (0..50).map(|i| (grid(i, i), i as u16)).collect();
+        let mut bytes = vec![];
+        tree.write_to(&mut bytes).unwrap();
+
+        let check = |bytes: &[u8]| {
+            let err = RTree::<u16>::read_from(&mut &bytes[..]).err().unwrap();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        };
+        for i in (0..bytes.len()).filter(|i| i % 7 == 0) {
+            let mut bad = bytes.clone();
+            bad[i] ^= 0x10;
+            check(&bad);
+        }
+        check(&bytes[..bytes.len() - 1]);
+        check(&bytes[..10]);
+        check(&[]);
+
+        // a node that lists the root as a child, or itself, is refused
+        // even if the checksum is right
+        let tree: RTree<u16> = (0..200).map(|i| (grid(i, i), i as u16)).collect();
+        let mut bytes = vec![];
+        tree.write_to(&mut bytes).unwrap();
+        let snapshot = Snapshot::<u16, i16>::new(&bytes).unwrap();
+        let root = snapshot.nodes - 1;
+        let child = snapshot.node(root).start;
+        for &(node, points_at) in &[(root, root), (child, root), (child, child)] {
+            let at = super::HEADER_SIZE + node * super::node_size::<i16>() + super::rect_size::<i16>() + 1;
+            let mut looped = bytes[..bytes.len() - super::CHECKSUM_SIZE].to_vec();
+            let mut range = vec![];
+            super::push_uint(&mut range, points_at as u64, 8);
+            super::push_uint(&mut range, points_at as u64 + 1, 8);
+            looped[at..at + 16].copy_from_slice(&range);
+            let sum = super::checksum(&looped);
+            super::push_uint(&mut looped, sum, 8);
+            assert!(Snapshot::<u16, i16>::new(&looped).is_err());
+            check(&looped);
+        }
+
+        // a leaf that covers an empty slot is refused
+        let snapshot = Snapshot::<u16, i16>::new(&bytes).unwrap();
+        let at = super::HEADER_SIZE + snapshot.nodes * super::node_size::<i16>() + super::rect_size::<i16>();
+        let mut emptied = bytes[..bytes.len() - super::CHECKSUM_SIZE].to_vec();
+        for b in &mut emptied[at..at + 8] {
+            *b = 0xff;
+        }
+        let sum = super::checksum(&emptied);
+        super::push_uint(&mut emptied, sum, 8);
+        check(&emptied);
+
+        // a newer version is refused even if the checksum is right
+        let mut newer = bytes[..bytes.len() - 8].to_vec();
+        newer[4] = super::VERSION as u8 + 1;
+        let sum = super::checksum(&newer);
+        super::push_uint(&mut newer, sum, 8);
+        check(&newer);
+    }
+}
@@ -0,0 +1,273 @@
+use std;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use ecs::{self, Join};
+use rtree::{Rectangle, SpatialIndex};
+use transform::Location;
+use Step;
+
+/// Entities that have been deleted but are still in the world. specs
+/// keeps the components of a deleted entity until the world is
+/// maintained, so systems should delete entities with `Deleted::delete`
+/// for spatial queries to stop finding them straight away. An entity
+/// stays here until the world has removed its `Location`, so it does
+/// not matter if the deleting system runs before or after the spatial
+/// index is updated.
+#[derive(Clone, Debug, Default)]
+pub struct Deleted(pub HashSet<ecs::Entity>);
+
+impl Deleted {
+    /// delete an entity and record it so spatial queries skip it
+    pub fn delete(&mut self, arg: &ecs::RunArg, eid: ecs::Entity) {
+        arg.delete(eid);
+        self.0.insert(eid);
+    }
+}
+
+/// Keeps the spatial index resource `S` in step with the `Location`
+/// of every entity
+pub struct System<S> {
+    /// the rectangle each entity was last seen at, and the
+    /// step it was last seen on
+    indexed: HashMap<ecs::Entity, (Rectangle, u64)>,
+    index: PhantomData<S>,
+}
+
+impl<S> System<S> {
+    pub fn new() -> System<S> {
+        System {
+            indexed: HashMap::new(),
+            index: PhantomData,
+        }
+    }
+}
+
+impl<S> ecs::System<Step> for System<S>
+    where S: SpatialIndex<ecs::Entity> + Send + Sync + 'static
+{
+    fn run(&mut self, arg: ecs::RunArg, step: Step) {
+        let (eids, location, mut tree, mut deleted) = arg.fetch(|w| {
+            (w.entities(),
+             w.read::<Location>(),
+             w.write_resource::<S>(),
+             w.write_resource::<Deleted>())
+        });
+
+        if !step.is_game() {
+            return
+        }
+
+        // once the world has been maintained these have lost their
+        // locations and are dropped from the index below
+        deleted.0.retain(|&eid| location.get(eid).is_some());
+
+        let now = step.step();
+        for (eid, loc) in (&eids, &location).iter() {
+            match self.indexed.entry(eid) {
+                Entry::Occupied(mut e) => *e.get_mut() = (loc.0, now),
+                Entry::Vacant(e) => {
+                    tree.insert(loc.0, eid);
+                    e.insert((loc.0, now));
+                }
+            }
+        }
+
+        // anything not seen has lost its location or been deleted. Entities
+        // deleted during this step keep their components until the world
+        // is maintained, so they are dropped on the following step.
+        let stale: Vec<_> = self.indexed
+            .iter()
+            .filter(|&(_, &(_, seen))| seen != now)
+            .map(|(&eid, &(rect, _))| (eid, rect))
+            .collect();
+        for (eid, rect) in stale {
+            tree.remove(rect, &eid);
+            self.indexed.remove(&eid);
+        }
+
+        // entities only move a little each step, so moving them in place
+        // is cheaper than finding and updating each one
+        let indexed = &self.indexed;
+        tree.refit(|rect, eid| indexed.get(eid).map_or(rect, |&(rect, _)| rect));
+    }
+}
+
+/// The spatial index combined with the entities deleted this step, for
+/// finding entities by area and joining them with their components
+///
+/// ```ignore
+/// let spatial = Spatial::new(&tree, &deleted);
+/// for (eid, (health, loc)) in spatial.query(rect).with((&health, &location)) {
+///     ...
+/// }
+/// ```
+pub struct Spatial<'a, S: 'a> {
+    tree: &'a S,
+    deleted: &'a Deleted,
+}
+
+impl<'a, S: SpatialIndex<ecs::Entity>> Spatial<'a, S> {
+    pub fn new(tree: &'a S, deleted: &'a Deleted) -> Spatial<'a, S> {
+        Spatial {
+            tree: tree,
+            deleted: deleted,
+        }
+    }
+
+    /// every living entity whose location intersects `rect`
+    pub fn query(&self, rect: Rectangle) -> Query<'a> {
+        Query {
+            iter: self.tree.query(rect).into_iter(),
+            deleted: self.deleted,
+        }
+    }
+}
+
+/// Something that components can be looked up in, implemented for
+/// storages and tuples of storages
+pub trait Lookup {
+    type Item;
+
+    /// the components of `eid`, if it has all of them
+    fn lookup(&self, eid: ecs::Entity) -> Option<Self::Item>;
+}
+
+impl<'a, T, A, D> Lookup for &'a ecs::Storage<T, A, D>
+    where T: ecs::Component,
+          A: Deref<Target = ecs::Allocator>,
+          D: Deref<Target = ecs::MaskedStorage<T>>
+{
+    type Item = &'a T;
+
+    fn lookup(&self, eid: ecs::Entity) -> Option<&'a T> {
+        (*self).get(eid)
+    }
+}
+
+impl<A: Lookup, B: Lookup> Lookup for (A, B) {
+    type Item = (A::Item, B::Item);
+
+    fn lookup(&self, eid: ecs::Entity) -> Option<Self::Item> {
+        self.0.lookup(eid).and_then(|a| self.1.lookup(eid).map(|b| (a, b)))
+    }
+}
+
+impl<A: Lookup, B: Lookup, C: Lookup> Lookup for (A, B, C) {
+    type Item = (A::Item, B::Item, C::Item);
+
+    fn lookup(&self, eid: ecs::Entity) -> Option<Self::Item> {
+        self.0.lookup(eid).and_then(|a| {
+            self.1.lookup(eid).and_then(|b| self.2.lookup(eid).map(|c| (a, b, c)))
+        })
+    }
+}
+
+/// The entities found by `Spatial::query`, entities deleted this
+/// step are skipped
+pub struct Query<'a> {
+    iter: std::vec::IntoIter<(Rectangle, &'a ecs::Entity)>,
+    deleted: &'a Deleted,
+}
+
+impl<'a> Query<'a> {
+    /// only keep the entities that have every component in `storages`,
+    /// yielding the entity with its components
+    pub fn with<L: Lookup>(self, storages: L) -> With<'a, L> {
+        With {
+            query: self,
+            storages: storages,
+        }
+    }
+}
+
+impl<'a> Iterator for Query<'a> {
+    type Item = ecs::Entity;
+
+    fn next(&mut self) -> Option<ecs::Entity> {
+        let deleted = self.deleted;
+        self.iter
+            .by_ref()
+            .map(|(_, &eid)| eid)
+            .find(|eid| !deleted.0.contains(eid))
+    }
+}
+
+/// The entities found by `Query::with` along with their components
+pub struct With<'a, L> {
+    query: Query<'a>,
+    storages: L,
+}
+
+impl<'a, L: Lookup> Iterator for With<'a, L> {
+    type Item = (ecs::Entity, L::Item);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(eid) = self.query.next() {
+            if let Some(found) = self.storages.lookup(eid) {
+                return Some((eid, found));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ecs;
+    use rtree::{RTree, Rectangle, Point};
+    use super::{Deleted, Spatial};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Tag(u8);
+
+    impl ecs::Component for Tag {
+        type Storage = ecs::VecStorage<Tag>;
+    }
+
+    fn rect(x: i16, y: i16) -> Rectangle {
+        Rectangle {
+            min: Point::new(x, y),
+            max: Point::new(x + 1, y + 1),
+        }
+    }
+
+    #[test]
+    fn query_with() {
+        let mut world = ecs::World::new();
+        world.register::<Tag>();
+        world.add_resource(RTree::<ecs::Entity>::new());
+        world.add_resource(Deleted::default());
+
+        let tagged = world.create_now().with(Tag(1)).build();
+        let untagged = world.create_now().build();
+        let deleted = world.create_now().with(Tag(2)).build();
+        let far = world.create_now().with(Tag(3)).build();
+        {
+            let mut tree = world.write_resource::<RTree<ecs::Entity>>();
+            tree.insert(rect(0, 0), tagged);
+            tree.insert(rect(2, 2), untagged);
+            tree.insert(rect(4, 4), deleted);
+            tree.insert(rect(100, 100), far);
+        }
+        world.delete_later(deleted);
+        world.write_resource::<Deleted>().0.insert(deleted);
+
+        let tree = world.read_resource::<RTree<ecs::Entity>>();
+        let recorded = world.read_resource::<Deleted>();
+        let tags = world.read::<Tag>();
+        let spatial = Spatial::new(&*tree, &*recorded);
+        let area = rect(0, 0).extend(rect(10, 10));
+
+        let found: Vec<_> = spatial.query(area).collect();
+        assert_eq!(2, found.len());
+        assert!(found.contains(&tagged) && found.contains(&untagged));
+
+        let found: Vec<_> = spatial.query(area).with(&tags).map(|(eid, tag)| (eid, tag.clone())).collect();
+        assert_eq!(vec![(tagged, Tag(1))], found);
+
+        let found: Vec<_> = spatial.query(rect(100, 100)).with(&tags).map(|(eid, _)| eid).collect();
+        assert_eq!(vec![far], found);
+    }
+}